@@ -0,0 +1,353 @@
+//! Fixation and gaze density maps.
+//!
+//! Fixations or samples from any number of trials are accumulated on a screen sized grid,
+//! one cell per pixel of the gaze coordinate system, and smoothed with a Gaussian kernel
+//! whose width is given in degrees of visual angle.
+
+use crate::common::Eye;
+use crate::decimal_to_f64;
+use crate::generic::{MetaData, Trial};
+use anyhow::anyhow;
+use image::{imageops, Rgb, RgbImage};
+use rayon::prelude::*;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weighting {
+    /// Each fixation adds its duration in ms
+    Duration,
+    /// Each fixation adds one
+    Count,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colormap {
+    Gray,
+    Hot,
+    Viridis,
+}
+
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    /// left, top, right, bottom of the gaze coordinates
+    pub bounds: [f64; 4],
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f64>,
+    resolution_sum: f64,
+    resolution_count: usize,
+}
+
+/// Smoothed and normalised density, summing to one.
+#[derive(Debug, Clone)]
+pub struct DensityMap {
+    pub width: usize,
+    pub height: usize,
+    pub values: Vec<f64>,
+}
+
+impl Heatmap {
+    pub fn new(bounds: [f64; 4]) -> Self {
+        let width = (bounds[2] - bounds[0] + 1.).max(1.) as usize;
+        let height = (bounds[3] - bounds[1] + 1.).max(1.) as usize;
+        Heatmap {
+            bounds,
+            width,
+            height,
+            data: vec![0.; width * height],
+            resolution_sum: 0.,
+            resolution_count: 0,
+        }
+    }
+
+    /// Heatmap covering the `GAZE_COORDS` of the recording.
+    pub fn from_meta(meta: &MetaData) -> anyhow::Result<Self> {
+        let coords = meta
+            .gaze_coords
+            .ok_or_else(|| anyhow!("Recording does not specify GAZE_COORDS"))?;
+        Ok(Self::new(coords.map(decimal_to_f64)))
+    }
+
+    fn add(&mut self, position: [f64; 2], weight: f64) {
+        let x = (position[0] - self.bounds[0]).floor();
+        let y = (position[1] - self.bounds[1]).floor();
+        if x >= 0. && y >= 0. && (x as usize) < self.width && (y as usize) < self.height {
+            self.data[y as usize * self.width + x as usize] += weight;
+        }
+    }
+
+    fn add_resolution(&mut self, resolution: Option<[f64; 2]>) {
+        if let Some(r) = resolution {
+            let r = (r[0] + r[1]) / 2.;
+            if r.is_finite() && r > 0. {
+                self.resolution_sum += r;
+                self.resolution_count += 1;
+            }
+        }
+    }
+
+    pub fn add_fixations(&mut self, trial: &Trial, eye: Eye, weighting: Weighting) {
+        for e in trial.events.iter().filter(|e| e.eye == eye) {
+            if let Some(position) = e.fixation_position() {
+                let weight = match weighting {
+                    Weighting::Duration => e.duration(),
+                    Weighting::Count => 1.,
+                };
+                self.add(position, weight);
+                self.add_resolution(e.resolution.map(|r| r.map(decimal_to_f64)));
+            }
+        }
+    }
+
+    pub fn add_samples(&mut self, trial: &Trial, eye: Eye) {
+        for s in &trial.samples {
+            if let Some(data) = s.eye(eye) {
+                self.add(data.position.map(decimal_to_f64), 1.);
+                self.add_resolution(s.resolution.map(|r| r.map(decimal_to_f64)));
+            }
+        }
+    }
+
+    /// Mean angular resolution (pixels per degree) of the accumulated data.
+    pub fn pixels_per_degree(&self) -> Option<f64> {
+        (self.resolution_count > 0).then(|| self.resolution_sum / self.resolution_count as f64)
+    }
+
+    /// Smooths the accumulated map with a Gaussian of standard deviation `sigma` degrees.
+    ///
+    /// The angular resolution recorded with the data is used unless `pixels_per_degree` is given.
+    pub fn density(
+        &self,
+        sigma: f64,
+        pixels_per_degree: Option<f64>,
+    ) -> anyhow::Result<DensityMap> {
+        let ppd = pixels_per_degree
+            .or_else(|| self.pixels_per_degree())
+            .ok_or_else(|| anyhow!("No angular resolution available, specify pixels per degree"))?;
+        let sigma_px = sigma * ppd;
+
+        let kernel = gaussian_kernel(sigma_px);
+        let radius = kernel.len() / 2;
+        let (w, h) = (self.width, self.height);
+
+        let mut horizontal = vec![0.; w * h];
+        horizontal
+            .par_chunks_mut(w)
+            .zip(self.data.par_chunks(w))
+            .for_each(|(out, row)| {
+                for (x, o) in out.iter_mut().enumerate() {
+                    *o = kernel
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(k, weight)| {
+                            let sx = (x + k).checked_sub(radius)?;
+                            row.get(sx).map(|v| v * weight)
+                        })
+                        .sum();
+                }
+            });
+
+        let mut values = vec![0.; w * h];
+        values.par_chunks_mut(w).enumerate().for_each(|(y, out)| {
+            for (k, weight) in kernel.iter().enumerate() {
+                let Some(sy) = (y + k).checked_sub(radius).filter(|sy| *sy < h) else {
+                    continue;
+                };
+                let row = &horizontal[sy * w..(sy + 1) * w];
+                for (o, v) in out.iter_mut().zip(row) {
+                    *o += v * weight;
+                }
+            }
+        });
+
+        let total: f64 = values.iter().sum();
+        if total > 0. {
+            values.iter_mut().for_each(|v| *v /= total);
+        }
+
+        Ok(DensityMap {
+            width: w,
+            height: h,
+            values,
+        })
+    }
+}
+
+fn gaussian_kernel(sigma: f64) -> Vec<f64> {
+    if sigma <= 0. {
+        return vec![1.];
+    }
+    let radius = (3. * sigma).ceil() as i64;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2. * sigma * sigma)).exp())
+        .collect();
+    let sum: f64 = kernel.iter().sum();
+    kernel.into_iter().map(|k| k / sum).collect()
+}
+
+impl Colormap {
+    pub fn color(&self, v: f64) -> [u8; 3] {
+        let v = v.clamp(0., 1.);
+        let stops: &[[f64; 3]] = match self {
+            Colormap::Gray => &[[0., 0., 0.], [255., 255., 255.]],
+            Colormap::Hot => &[
+                [0., 0., 0.],
+                [230., 0., 0.],
+                [255., 210., 0.],
+                [255., 255., 255.],
+            ],
+            Colormap::Viridis => &[
+                [68., 1., 84.],
+                [59., 82., 139.],
+                [33., 145., 140.],
+                [94., 201., 98.],
+                [253., 231., 37.],
+            ],
+        };
+        let pos = v * (stops.len() - 1) as f64;
+        let i = (pos.floor() as usize).min(stops.len() - 2);
+        let t = pos - i as f64;
+        let (a, b) = (stops[i], stops[i + 1]);
+        [0, 1, 2].map(|c| (a[c] + (b[c] - a[c]) * t).round() as u8)
+    }
+}
+
+impl DensityMap {
+    pub fn max(&self) -> f64 {
+        self.values.iter().copied().fold(0., f64::max)
+    }
+
+    /// Renders the map scaled to its maximum. With a `stimulus` image the map is blended over
+    /// it with an opacity proportional to the density, at most `opacity`.
+    pub fn to_image(
+        &self,
+        colormap: Colormap,
+        stimulus: Option<&Path>,
+        opacity: f64,
+    ) -> anyhow::Result<RgbImage> {
+        let max = self.max();
+        let scale = if max > 0. { 1. / max } else { 0. };
+
+        let background = stimulus
+            .map(|path| -> anyhow::Result<RgbImage> {
+                let img = image::open(path)?.to_rgb8();
+                Ok(imageops::resize(
+                    &img,
+                    self.width as u32,
+                    self.height as u32,
+                    imageops::FilterType::Triangle,
+                ))
+            })
+            .transpose()?;
+
+        Ok(RgbImage::from_fn(
+            self.width as u32,
+            self.height as u32,
+            |x, y| {
+                let v = self.values[y as usize * self.width + x as usize] * scale;
+                let color = colormap.color(v);
+                match &background {
+                    Some(bg) => {
+                        let base = bg.get_pixel(x, y).0;
+                        let alpha = v * opacity;
+                        Rgb([0, 1, 2].map(|c| {
+                            (base[c] as f64 * (1. - alpha) + color[c] as f64 * alpha).round() as u8
+                        }))
+                    }
+                    None => Rgb(color),
+                }
+            },
+        ))
+    }
+
+    pub fn write_png<P: AsRef<Path>>(
+        &self,
+        path: P,
+        colormap: Colormap,
+        stimulus: Option<&Path>,
+        opacity: f64,
+    ) -> anyhow::Result<()> {
+        self.to_image(colormap, stimulus, opacity)?
+            .save_with_format(path, image::ImageFormat::Png)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::{EventInfo, EventRecord, TimeRecord};
+    use crate::Decimal;
+    use std::str::FromStr;
+
+    fn fixation(end: i64, x: &str, y: &str) -> EventRecord {
+        EventRecord {
+            time_record: TimeRecord {
+                start: Decimal::from(0),
+                end: Decimal::from(end),
+            },
+            eye: Eye::Left,
+            resolution: None,
+            info: EventInfo::Fixation {
+                average_position: [Decimal::from_str(x).unwrap(), Decimal::from_str(y).unwrap()],
+                average_pupil_area: Decimal::from(1000),
+            },
+        }
+    }
+
+    #[test]
+    fn test_binning() {
+        let trial = Trial {
+            events: vec![
+                fixation(100, "2.5", "3.7"),
+                fixation(50, "2.9", "3.1"),
+                fixation(200, "7", "1"),
+                // Outside of the bounds
+                fixation(300, "10", "4"),
+            ],
+            ..Trial::empty(1, TimeRecord::default())
+        };
+        let mut heatmap = Heatmap::new([0., 0., 9., 9.]);
+        assert_eq!((heatmap.width, heatmap.height), (10, 10));
+
+        heatmap.add_fixations(&trial, Eye::Left, Weighting::Duration);
+        assert_eq!(heatmap.data[3 * 10 + 2], 150.);
+        assert_eq!(heatmap.data[10 + 7], 200.);
+        assert_eq!(heatmap.data.iter().sum::<f64>(), 350.);
+
+        let mut counts = Heatmap::new([0., 0., 9., 9.]);
+        counts.add_fixations(&trial, Eye::Left, Weighting::Count);
+        counts.add_fixations(&trial, Eye::Right, Weighting::Count);
+        assert_eq!(counts.data[3 * 10 + 2], 2.);
+        assert_eq!(counts.data.iter().sum::<f64>(), 3.);
+    }
+
+    #[test]
+    fn test_smoothing() {
+        let mut heatmap = Heatmap::new([0., 0., 9., 9.]);
+        let trial = Trial {
+            events: vec![fixation(100, "5", "5")],
+            ..Trial::empty(1, TimeRecord::default())
+        };
+        heatmap.add_fixations(&trial, Eye::Left, Weighting::Count);
+        assert!(heatmap.density(1., None).is_err());
+
+        // Without smoothing the density is the normalised map
+        let density = heatmap.density(0., Some(30.)).unwrap();
+        assert_eq!(density.values[5 * 10 + 5], 1.);
+        assert_eq!(density.values.iter().sum::<f64>(), 1.);
+
+        // A sigma of one pixel spreads the point over the separable kernel
+        let density = heatmap.density(1. / 30., Some(30.)).unwrap();
+        let kernel = gaussian_kernel(1.);
+        assert_eq!(kernel.len(), 7);
+        let at = |x: usize, y: usize| density.values[y * 10 + x];
+        assert!((at(5, 5) - kernel[3] * kernel[3]).abs() < 1e-12);
+        assert!((at(4, 5) - kernel[2] * kernel[3]).abs() < 1e-12);
+        assert!((at(4, 5) - at(6, 5)).abs() < 1e-12);
+        assert!((at(5, 3) - at(7, 5)).abs() < 1e-12);
+        assert_eq!(density.max(), at(5, 5));
+        assert_eq!(at(1, 5), 0.);
+        assert!((density.values.iter().sum::<f64>() - 1.).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,352 @@
+//! Saccadic main sequence fitting.
+//!
+//! Fits the amplitude–peak velocity and amplitude–duration relationships of all saccades of
+//! one eye in an experiment and flags saccades that fall far from the fitted curves.
+//! Residuals are computed on a log scale, `ln(observed / predicted)`, since the spread of the
+//! main sequence grows with amplitude.
+
+use crate::analysis::{least_squares, median};
+use crate::common::Eye;
+use crate::generic::Experiment;
+use anyhow::{anyhow, Context};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MainSequenceModel {
+    /// `y = a * x^b`
+    Power,
+    /// `y = a * (1 - exp(-x / b))`
+    Exponential,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CurveFit {
+    pub model: MainSequenceModel,
+    pub a: f64,
+    pub b: f64,
+    /// Standard deviation of the log residuals
+    pub residual_sd: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct MainSequenceOptions {
+    pub peak_velocity_model: MainSequenceModel,
+    pub duration_model: MainSequenceModel,
+    /// Saccades whose residual deviates from the median residual by more than this many
+    /// robust standard deviations (scaled MAD) are flagged as outliers.
+    pub max_residual: f64,
+    /// Saccades smaller than this amplitude (degrees) are ignored
+    pub min_amplitude: f64,
+}
+
+impl Default for MainSequenceOptions {
+    fn default() -> Self {
+        MainSequenceOptions {
+            peak_velocity_model: MainSequenceModel::Exponential,
+            duration_model: MainSequenceModel::Power,
+            max_residual: 3.,
+            min_amplitude: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SaccadeResidual {
+    pub trial_id: u32,
+    /// Index of the saccade in `Trial::events`
+    pub event_index: usize,
+    pub amplitude: f64,
+    pub peak_velocity: f64,
+    pub duration: f64,
+    pub peak_velocity_residual: f64,
+    pub duration_residual: f64,
+    pub outlier: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct MainSequence {
+    pub eye: Eye,
+    pub peak_velocity: CurveFit,
+    pub duration: CurveFit,
+    pub saccades: Vec<SaccadeResidual>,
+}
+
+impl CurveFit {
+    pub fn predict(&self, x: f64) -> f64 {
+        match self.model {
+            MainSequenceModel::Power => self.a * x.powf(self.b),
+            MainSequenceModel::Exponential => self.a * (1. - (-x / self.b).exp()),
+        }
+    }
+
+    pub fn log_residual(&self, x: f64, y: f64) -> f64 {
+        (y / self.predict(x)).ln()
+    }
+
+    pub fn fit(model: MainSequenceModel, x: &[f64], y: &[f64]) -> anyhow::Result<Self> {
+        let (a, b) = match model {
+            MainSequenceModel::Power => fit_power(x, y),
+            MainSequenceModel::Exponential => fit_exponential(x, y),
+        }
+        .ok_or_else(|| anyhow!("Not enough saccades to fit a {model:?} main sequence"))?;
+
+        let mut fit = CurveFit {
+            model,
+            a,
+            b,
+            residual_sd: 0.,
+        };
+        let residuals: Vec<f64> = x
+            .iter()
+            .zip(y)
+            .map(|(&x, &y)| fit.log_residual(x, y))
+            .collect();
+        let n = residuals.len() as f64;
+        let mean = residuals.iter().sum::<f64>() / n;
+        fit.residual_sd = (residuals.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / n).sqrt();
+        Ok(fit)
+    }
+}
+
+/// Linear regression in log-log space.
+fn fit_power(x: &[f64], y: &[f64]) -> Option<(f64, f64)> {
+    let rows: Vec<Vec<f64>> = x.iter().map(|x| vec![1., x.ln()]).collect();
+    let targets: Vec<f64> = y.iter().map(|y| y.ln()).collect();
+    let coef = least_squares(&rows, &targets)?;
+    Some((coef[0].exp(), coef[1]))
+}
+
+/// For a fixed `b` the optimal `a` has a closed form, so only `b` is searched: first on a
+/// logarithmic grid, then refined with a golden section search.
+fn fit_exponential(x: &[f64], y: &[f64]) -> Option<(f64, f64)> {
+    if x.len() < 2 {
+        return None;
+    }
+
+    let solve = |b: f64| {
+        let g: Vec<f64> = x.iter().map(|x| 1. - (-x / b).exp()).collect();
+        let a =
+            g.iter().zip(y).map(|(g, y)| g * y).sum::<f64>() / g.iter().map(|g| g * g).sum::<f64>();
+        let sse: f64 = g.iter().zip(y).map(|(g, y)| (y - a * g).powi(2)).sum();
+        (a, sse)
+    };
+
+    let max_x = x.iter().copied().fold(0., f64::max);
+    let grid: Vec<f64> = (0..=60)
+        .map(|i| max_x * 1e-3 * 10f64.powf(i as f64 * 0.1))
+        .collect();
+    let best = (0..grid.len()).min_by(|&i, &j| solve(grid[i]).1.total_cmp(&solve(grid[j]).1))?;
+
+    let mut lo = grid[best.saturating_sub(1)];
+    let mut hi = grid[(best + 1).min(grid.len() - 1)];
+    let phi = (5f64.sqrt() - 1.) / 2.;
+    for _ in 0..60 {
+        let m1 = hi - phi * (hi - lo);
+        let m2 = lo + phi * (hi - lo);
+        if solve(m1).1 < solve(m2).1 {
+            hi = m2;
+        } else {
+            lo = m1;
+        }
+    }
+    let b = (lo + hi) / 2.;
+    let (a, _) = solve(b);
+    (a.is_finite() && b > 0.).then_some((a, b))
+}
+
+/// Flags values deviating from the median by more than `k` scaled median absolute deviations.
+fn robust_outliers(values: &[f64], k: f64) -> Vec<bool> {
+    let Some(center) = median(values) else {
+        return Vec::new();
+    };
+    let deviations: Vec<f64> = values.iter().map(|v| (v - center).abs()).collect();
+    let mad = median(&deviations).unwrap_or_default() * 1.4826;
+    values
+        .iter()
+        .map(|v| mad > 0. && (v - center).abs() > k * mad)
+        .collect()
+}
+
+/// Fits the curve, then refits on the saccades not flagged as outliers so that artifacts do
+/// not bias the fit. Returns the fit together with the residuals and outlier flags.
+fn fit_robust(
+    model: MainSequenceModel,
+    x: &[f64],
+    y: &[f64],
+    k: f64,
+) -> anyhow::Result<(CurveFit, Vec<f64>, Vec<bool>)> {
+    let residuals_of = |fit: &CurveFit| -> Vec<f64> {
+        x.iter()
+            .zip(y)
+            .map(|(&x, &y)| fit.log_residual(x, y))
+            .collect()
+    };
+
+    let fit = CurveFit::fit(model, x, y)?;
+    let outliers = robust_outliers(&residuals_of(&fit), k);
+    let (inlier_x, inlier_y): (Vec<f64>, Vec<f64>) = x
+        .iter()
+        .zip(y)
+        .zip(&outliers)
+        .filter(|(_, outlier)| !**outlier)
+        .map(|((&x, &y), _)| (x, y))
+        .unzip();
+
+    let fit = CurveFit::fit(model, &inlier_x, &inlier_y).unwrap_or(fit);
+    let residuals = residuals_of(&fit);
+    let outliers = robust_outliers(&residuals, k);
+    Ok((fit, residuals, outliers))
+}
+
+impl Experiment {
+    pub fn main_sequence(
+        &self,
+        eye: Eye,
+        options: &MainSequenceOptions,
+    ) -> anyhow::Result<MainSequence> {
+        let mut saccades = Vec::new();
+        for trial in &self.trials {
+            for (i, e) in trial.events.iter().enumerate() {
+                if e.eye != eye {
+                    continue;
+                }
+                if let (Some(amplitude), Some(peak_velocity)) =
+                    (e.saccade_amplitude(), e.peak_velocity())
+                {
+                    let duration = e.duration();
+                    if amplitude >= options.min_amplitude && peak_velocity > 0. && duration > 0. {
+                        saccades.push((trial.id, i, amplitude, peak_velocity, duration));
+                    }
+                }
+            }
+        }
+
+        let amplitudes: Vec<f64> = saccades.iter().map(|s| s.2).collect();
+        let velocities: Vec<f64> = saccades.iter().map(|s| s.3).collect();
+        let durations: Vec<f64> = saccades.iter().map(|s| s.4).collect();
+
+        let (peak_velocity, velocity_residuals, velocity_outliers) = fit_robust(
+            options.peak_velocity_model,
+            &amplitudes,
+            &velocities,
+            options.max_residual,
+        )?;
+        let (duration, duration_residuals, duration_outliers) = fit_robust(
+            options.duration_model,
+            &amplitudes,
+            &durations,
+            options.max_residual,
+        )?;
+
+        let saccades = saccades
+            .iter()
+            .enumerate()
+            .map(
+                |(i, &(trial_id, event_index, amplitude, peak_velocity, duration))| {
+                    SaccadeResidual {
+                        trial_id,
+                        event_index,
+                        amplitude,
+                        peak_velocity,
+                        duration,
+                        peak_velocity_residual: velocity_residuals[i],
+                        duration_residual: duration_residuals[i],
+                        outlier: velocity_outliers[i] || duration_outliers[i],
+                    }
+                },
+            )
+            .collect();
+
+        Ok(MainSequence {
+            eye,
+            peak_velocity,
+            duration,
+            saccades,
+        })
+    }
+
+    /// Main sequence fits for every eye with saccades in the experiment. Eyes without any
+    /// saccade are skipped, a fit failing for an eye with saccades is an error.
+    pub fn main_sequences(
+        &self,
+        options: &MainSequenceOptions,
+    ) -> anyhow::Result<Vec<MainSequence>> {
+        [Eye::Left, Eye::Right]
+            .into_iter()
+            .filter(|&eye| {
+                self.trials
+                    .iter()
+                    .flat_map(|t| &t.events)
+                    .any(|e| e.eye == eye && e.is_saccade())
+            })
+            .map(|eye| {
+                self.main_sequence(eye, options)
+                    .with_context(|| format!("Could not fit the main sequence of the {eye:?} eye"))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_power_fit_and_outliers() {
+        let mut x: Vec<f64> = (1..40).map(|i| i as f64 * 0.5).collect();
+        let mut y: Vec<f64> = x.iter().map(|x| 20. * x.powf(0.6)).collect();
+        // small deterministic noise so the MAD is non-zero
+        for (i, v) in y.iter_mut().enumerate() {
+            *v *= 1. + 0.01 * ((i % 5) as f64 - 2.);
+        }
+        x.push(5.);
+        y.push(400.);
+
+        let (fit, _, outliers) = fit_robust(MainSequenceModel::Power, &x, &y, 3.).unwrap();
+        assert!((fit.a - 20.).abs() < 1.);
+        assert!((fit.b - 0.6).abs() < 0.01);
+        assert!(*outliers.last().unwrap());
+        assert_eq!(outliers.iter().filter(|o| **o).count(), 1);
+    }
+
+    #[test]
+    fn test_exponential_fit() {
+        let x: Vec<f64> = (1..60).map(|i| i as f64 * 0.5).collect();
+        let y: Vec<f64> = x.iter().map(|x| 600. * (1. - (-x / 8.).exp())).collect();
+
+        let fit = CurveFit::fit(MainSequenceModel::Exponential, &x, &y).unwrap();
+        assert!((fit.a - 600.).abs() < 1.);
+        assert!((fit.b - 8.).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_main_sequences_reports_failed_fits() {
+        use crate::generic::{EventInfo, EventRecord, TimeRecord, Trial};
+        use crate::Decimal;
+
+        let saccade = EventRecord {
+            time_record: TimeRecord {
+                start: Decimal::from(0),
+                end: Decimal::from(30),
+            },
+            eye: Eye::Left,
+            resolution: Some([Decimal::from(10), Decimal::from(10)]),
+            info: EventInfo::Saccade {
+                start_position: Some([Decimal::from(0), Decimal::from(0)]),
+                end_position: Some([Decimal::from(30), Decimal::from(40)]),
+                movement_angle: None,
+                peak_velocity: Decimal::from(300),
+            },
+        };
+        let mut exp = Experiment {
+            meta: Default::default(),
+            variable_labels: Vec::new(),
+            trials: vec![Trial::empty(1, TimeRecord::default())],
+        };
+        let options = MainSequenceOptions::default();
+        // Eyes without saccades are skipped
+        assert!(exp.main_sequences(&options).unwrap().is_empty());
+        // A single saccade is not enough to fit the left eye
+        exp.trials[0].events.push(saccade);
+        assert!(exp.main_sequences(&options).is_err());
+    }
+}
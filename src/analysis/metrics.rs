@@ -0,0 +1,361 @@
+//! Standard per-trial oculomotor statistics computed from parsed events.
+
+use crate::analysis::{mean, median};
+use crate::common::Eye;
+use crate::decimal_to_f64;
+use crate::generic::{EventInfo, EventRecord, Experiment, Trial};
+use anyhow::anyhow;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone)]
+pub struct TrialMetrics {
+    pub trial_id: u32,
+    pub eye: Eye,
+    pub fixation_count: usize,
+    /// Mean fixation duration in ms
+    pub fixation_duration_mean: Option<f64>,
+    /// Median fixation duration in ms
+    pub fixation_duration_median: Option<f64>,
+    /// Summed fixation duration in ms
+    pub total_dwell: f64,
+    pub saccade_count: usize,
+    pub saccade_duration_mean: Option<f64>,
+    pub saccade_duration_median: Option<f64>,
+    /// Mean saccade amplitude in degrees
+    pub saccade_amplitude_mean: Option<f64>,
+    /// Summed saccade amplitude in degrees
+    pub saccade_amplitude_total: f64,
+    /// Mean saccade peak velocity in degrees per second
+    pub peak_velocity_mean: Option<f64>,
+    pub blink_count: usize,
+    /// Summed distance between consecutive fixations in screen coordinates
+    pub scanpath_length: f64,
+}
+
+/// Trial metrics averaged over all trials sharing a trial variable value.
+#[derive(Debug, Clone)]
+pub struct ConditionMetrics {
+    pub label: String,
+    pub value: String,
+    pub eye: Eye,
+    pub n_trials: usize,
+    pub fixation_count: f64,
+    pub fixation_duration_mean: Option<f64>,
+    pub fixation_duration_median: Option<f64>,
+    pub total_dwell: f64,
+    pub saccade_count: f64,
+    pub saccade_duration_mean: Option<f64>,
+    pub saccade_duration_median: Option<f64>,
+    pub saccade_amplitude_mean: Option<f64>,
+    pub saccade_amplitude_total: f64,
+    pub peak_velocity_mean: Option<f64>,
+    pub blink_count: f64,
+    pub scanpath_length: f64,
+}
+
+impl EventRecord {
+    /// Event duration in ms
+    pub fn duration(&self) -> f64 {
+        decimal_to_f64(self.time_record.end) - decimal_to_f64(self.time_record.start)
+    }
+
+    pub fn is_fixation(&self) -> bool {
+        matches!(self.info, EventInfo::Fixation { .. })
+    }
+
+    pub fn is_saccade(&self) -> bool {
+        matches!(self.info, EventInfo::Saccade { .. })
+    }
+
+    pub fn is_blink(&self) -> bool {
+        matches!(self.info, EventInfo::Blink)
+    }
+
+    /// Saccade amplitude in degrees, computed from the start and end positions and the
+    /// angular resolution (pixels per degree) of the event.
+    pub fn saccade_amplitude(&self) -> Option<f64> {
+        match self.info {
+            EventInfo::Saccade {
+                start_position: Some(start),
+                end_position: Some(end),
+                ..
+            } => {
+                let res = self.resolution?;
+                let dx =
+                    (decimal_to_f64(end[0]) - decimal_to_f64(start[0])) / decimal_to_f64(res[0]);
+                let dy =
+                    (decimal_to_f64(end[1]) - decimal_to_f64(start[1])) / decimal_to_f64(res[1]);
+                let amplitude = dx.hypot(dy);
+                amplitude.is_finite().then_some(amplitude)
+            }
+            _ => None,
+        }
+    }
+
+    /// Direction of a saccade in degrees, `atan2` of the end minus the start position. Screen
+    /// coordinates grow downwards, so positive angles point down.
+    pub fn saccade_angle(&self) -> Option<f64> {
+        match self.info {
+            EventInfo::Saccade {
+                start_position: Some(start),
+                end_position: Some(end),
+                ..
+            } => {
+                let dx = decimal_to_f64(end[0]) - decimal_to_f64(start[0]);
+                let dy = decimal_to_f64(end[1]) - decimal_to_f64(start[1]);
+                Some(dy.atan2(dx).to_degrees())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn peak_velocity(&self) -> Option<f64> {
+        match self.info {
+            EventInfo::Saccade { peak_velocity, .. } => Some(decimal_to_f64(peak_velocity)),
+            _ => None,
+        }
+    }
+
+    pub fn fixation_position(&self) -> Option<[f64; 2]> {
+        match self.info {
+            EventInfo::Fixation {
+                average_position, ..
+            } => Some([
+                decimal_to_f64(average_position[0]),
+                decimal_to_f64(average_position[1]),
+            ]),
+            _ => None,
+        }
+    }
+}
+
+impl TrialMetrics {
+    pub fn from_trial(trial: &Trial, eye: Eye) -> Self {
+        let events: Vec<&EventRecord> = trial.events.iter().filter(|e| e.eye == eye).collect();
+
+        let fixation_durations: Vec<f64> = events
+            .iter()
+            .filter(|e| e.is_fixation())
+            .map(|e| e.duration())
+            .collect();
+        let saccades: Vec<&&EventRecord> = events.iter().filter(|e| e.is_saccade()).collect();
+        let saccade_durations: Vec<f64> = saccades.iter().map(|e| e.duration()).collect();
+        let amplitudes: Vec<f64> = saccades
+            .iter()
+            .filter_map(|e| e.saccade_amplitude())
+            .collect();
+        let peak_velocities: Vec<f64> = saccades.iter().filter_map(|e| e.peak_velocity()).collect();
+
+        let positions: Vec<[f64; 2]> = events
+            .iter()
+            .filter_map(|e| e.fixation_position())
+            .collect();
+        let scanpath_length = positions
+            .windows(2)
+            .map(|w| (w[1][0] - w[0][0]).hypot(w[1][1] - w[0][1]))
+            .sum();
+
+        TrialMetrics {
+            trial_id: trial.id,
+            eye,
+            fixation_count: fixation_durations.len(),
+            fixation_duration_mean: mean(&fixation_durations),
+            fixation_duration_median: median(&fixation_durations),
+            total_dwell: fixation_durations.iter().sum(),
+            saccade_count: saccades.len(),
+            saccade_duration_mean: mean(&saccade_durations),
+            saccade_duration_median: median(&saccade_durations),
+            saccade_amplitude_mean: mean(&amplitudes),
+            saccade_amplitude_total: amplitudes.iter().sum(),
+            peak_velocity_mean: mean(&peak_velocities),
+            blink_count: events.iter().filter(|e| e.is_blink()).count(),
+            scanpath_length,
+        }
+    }
+}
+
+impl ConditionMetrics {
+    fn aggregate(label: &str, value: &str, eye: Eye, trials: &[&TrialMetrics]) -> Self {
+        let avg = |f: &dyn Fn(&TrialMetrics) -> f64| {
+            let values: Vec<f64> = trials.iter().map(|t| f(t)).collect();
+            mean(&values).unwrap_or_default()
+        };
+        let avg_opt = |f: &dyn Fn(&TrialMetrics) -> Option<f64>| {
+            let values: Vec<f64> = trials.iter().filter_map(|t| f(t)).collect();
+            mean(&values)
+        };
+
+        ConditionMetrics {
+            label: label.to_string(),
+            value: value.to_string(),
+            eye,
+            n_trials: trials.len(),
+            fixation_count: avg(&|t| t.fixation_count as f64),
+            fixation_duration_mean: avg_opt(&|t| t.fixation_duration_mean),
+            fixation_duration_median: avg_opt(&|t| t.fixation_duration_median),
+            total_dwell: avg(&|t| t.total_dwell),
+            saccade_count: avg(&|t| t.saccade_count as f64),
+            saccade_duration_mean: avg_opt(&|t| t.saccade_duration_mean),
+            saccade_duration_median: avg_opt(&|t| t.saccade_duration_median),
+            saccade_amplitude_mean: avg_opt(&|t| t.saccade_amplitude_mean),
+            saccade_amplitude_total: avg(&|t| t.saccade_amplitude_total),
+            peak_velocity_mean: avg_opt(&|t| t.peak_velocity_mean),
+            blink_count: avg(&|t| t.blink_count as f64),
+            scanpath_length: avg(&|t| t.scanpath_length),
+        }
+    }
+}
+
+impl Trial {
+    /// Metrics for every eye with events in the trial.
+    pub fn metrics(&self) -> Vec<TrialMetrics> {
+        [Eye::Left, Eye::Right]
+            .into_iter()
+            .filter(|&eye| self.events.iter().any(|e| e.eye == eye))
+            .map(|eye| TrialMetrics::from_trial(self, eye))
+            .collect()
+    }
+}
+
+impl Experiment {
+    pub fn trial_metrics(&self) -> Vec<TrialMetrics> {
+        self.trials.par_iter().flat_map(|t| t.metrics()).collect()
+    }
+
+    /// Groups trials by the values of the trial variable `label` and averages the trial
+    /// metrics within each group and eye.
+    pub fn condition_metrics(&self, label: &str) -> anyhow::Result<Vec<ConditionMetrics>> {
+        let idx = self
+            .variable_index(label)
+            .ok_or_else(|| anyhow!("Unknown trial variable: {label}"))?;
+
+        let metrics: Vec<(&Trial, Vec<TrialMetrics>)> =
+            self.trials.par_iter().map(|t| (t, t.metrics())).collect();
+
+        let mut groups: BTreeMap<(&str, Eye), Vec<&TrialMetrics>> = BTreeMap::new();
+        for (trial, trial_metrics) in &metrics {
+            let value = trial.variables.get(idx).map_or("", |v| v.as_str());
+            for m in trial_metrics {
+                groups.entry((value, m.eye)).or_default().push(m);
+            }
+        }
+
+        Ok(groups
+            .into_iter()
+            .map(|((value, eye), trials)| ConditionMetrics::aggregate(label, value, eye, &trials))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::{Position, TimeRecord};
+    use crate::Decimal;
+
+    fn pos(x: i64, y: i64) -> Position {
+        [Decimal::from(x), Decimal::from(y)]
+    }
+
+    fn event(start: i64, end: i64, eye: Eye, info: EventInfo) -> EventRecord {
+        EventRecord {
+            time_record: TimeRecord {
+                start: Decimal::from(start),
+                end: Decimal::from(end),
+            },
+            eye,
+            resolution: Some(pos(10, 10)),
+            info,
+        }
+    }
+
+    fn fixation(start: i64, end: i64, x: i64, y: i64) -> EventRecord {
+        let info = EventInfo::Fixation {
+            average_position: pos(x, y),
+            average_pupil_area: Decimal::from(1000),
+        };
+        event(start, end, Eye::Left, info)
+    }
+
+    #[test]
+    fn test_trial_metrics() {
+        let saccade = EventInfo::Saccade {
+            start_position: Some(pos(0, 0)),
+            end_position: Some(pos(30, 40)),
+            movement_angle: None,
+            peak_velocity: Decimal::from(300),
+        };
+        let trial = Trial {
+            events: vec![
+                fixation(0, 100, 0, 0),
+                event(100, 130, Eye::Left, saccade),
+                fixation(130, 330, 30, 40),
+                fixation(400, 430, 30, 40),
+                event(330, 400, Eye::Right, EventInfo::Blink),
+            ],
+            ..Trial::empty(1, TimeRecord::default())
+        };
+
+        let metrics = trial.metrics();
+        assert_eq!(metrics.len(), 2);
+        let left = &metrics[0];
+        assert_eq!(left.eye, Eye::Left);
+        assert_eq!(left.fixation_count, 3);
+        assert_eq!(left.fixation_duration_mean, Some(110.));
+        assert_eq!(left.fixation_duration_median, Some(100.));
+        assert_eq!(left.total_dwell, 330.);
+        assert_eq!(left.saccade_count, 1);
+        assert_eq!(left.saccade_duration_mean, Some(30.));
+        // 50 pixels at 10 pixels per degree
+        assert_eq!(left.saccade_amplitude_mean, Some(5.));
+        assert_eq!(left.peak_velocity_mean, Some(300.));
+        assert_eq!(left.blink_count, 0);
+        assert_eq!(left.scanpath_length, 50.);
+
+        let right = &metrics[1];
+        assert_eq!(right.eye, Eye::Right);
+        assert_eq!(right.fixation_count, 0);
+        assert_eq!(right.fixation_duration_mean, None);
+        assert_eq!(right.blink_count, 1);
+    }
+
+    #[test]
+    fn test_condition_metrics() {
+        let exp = Experiment {
+            meta: Default::default(),
+            variable_labels: vec!["condition".to_string()],
+            trials: [
+                (1, "a", vec![fixation(0, 100, 0, 0)]),
+                (
+                    2,
+                    "a",
+                    vec![fixation(0, 300, 0, 0), fixation(300, 400, 0, 0)],
+                ),
+                (3, "b", vec![fixation(0, 50, 0, 0)]),
+            ]
+            .into_iter()
+            .map(|(id, condition, events)| Trial {
+                events,
+                variables: vec![condition.to_string()],
+                ..Trial::empty(id, TimeRecord::default())
+            })
+            .collect(),
+        };
+
+        let conditions = exp.condition_metrics("condition").unwrap();
+        assert_eq!(conditions.len(), 2);
+        let a = &conditions[0];
+        assert_eq!((a.value.as_str(), a.n_trials), ("a", 2));
+        assert_eq!(a.fixation_count, 1.5);
+        // Mean of the trial means 100 and 200
+        assert_eq!(a.fixation_duration_mean, Some(150.));
+        assert_eq!(a.total_dwell, 250.);
+        assert_eq!(a.saccade_duration_mean, None);
+        let b = &conditions[1];
+        assert_eq!((b.value.as_str(), b.n_trials), ("b", 1));
+        assert_eq!(b.total_dwell, 50.);
+
+        assert!(exp.condition_metrics("block").is_err());
+    }
+}
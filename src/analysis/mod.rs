@@ -0,0 +1,69 @@
+pub mod heatmap;
+pub mod main_sequence;
+pub mod metrics;
+pub mod quality;
+pub mod recalibration;
+pub mod scanpath;
+
+pub(crate) fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+pub(crate) fn median(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let mid = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        Some((sorted[mid - 1] + sorted[mid]) / 2.)
+    } else {
+        Some(sorted[mid])
+    }
+}
+
+/// Solves the linear least squares problem `rows * x = targets` through the normal equations.
+///
+/// Returns `None` when the system is underdetermined or singular.
+pub(crate) fn least_squares(rows: &[Vec<f64>], targets: &[f64]) -> Option<Vec<f64>> {
+    let n = rows.first()?.len();
+    if rows.len() < n || rows.len() != targets.len() {
+        return None;
+    }
+
+    // Augmented normal matrix [A^T A | A^T b]
+    let mut m = vec![vec![0.; n + 1]; n];
+    for (row, &b) in rows.iter().zip(targets) {
+        for i in 0..n {
+            for j in 0..n {
+                m[i][j] += row[i] * row[j];
+            }
+            m[i][n] += row[i] * b;
+        }
+    }
+
+    // Gaussian elimination with partial pivoting
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&a, &b| m[a][col].abs().total_cmp(&m[b][col].abs()))?;
+        if m[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        m.swap(col, pivot);
+        let (upper, lower) = m.split_at_mut(col + 1);
+        let pivot_row = &upper[col];
+        for row in lower {
+            let factor = row[col] / pivot_row[col];
+            for (x, p) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *x -= factor * p;
+            }
+        }
+    }
+
+    let mut x = vec![0.; n];
+    for r in (0..n).rev() {
+        let sum: f64 = (r + 1..n).map(|c| m[r][c] * x[c]).sum();
+        x[r] = (m[r][n] - sum) / m[r][r];
+    }
+    Some(x)
+}
@@ -0,0 +1,251 @@
+//! Tracking loss and data quality summaries.
+
+use crate::common::Eye;
+use crate::decimal_to_f64;
+use crate::generic::{CRStatus, EventInfo, Experiment, MetaData, Trial};
+use rayon::prelude::*;
+
+#[cfg(feature = "py-ext")]
+use pyo3::prelude::*;
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "py-ext", pyclass(get_all))]
+pub struct TrialQuality {
+    pub trial_id: u32,
+    pub n_samples: usize,
+    /// Trial duration in ms
+    pub duration: f64,
+    /// Sampling rate in Hz achieved in the trial
+    pub sampling_rate: Option<f64>,
+    /// Sampling rate in Hz requested by the recording configuration
+    pub configured_rate: Option<f64>,
+    pub eyes: Vec<EyeQuality>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "py-ext", pyclass(get_all))]
+pub struct EyeQuality {
+    pub eye: Eye,
+    /// Proportion of samples without gaze data
+    pub missing_gaze: f64,
+    /// Proportion of samples with the corneal reflection missing
+    pub cr_missing: f64,
+    /// Proportion of samples with the corneal reflection recovering
+    pub cr_recovering: f64,
+    /// Proportion of samples flagged as interpolated
+    pub interpolated: f64,
+    /// Longest run of missing gaze in ms
+    pub longest_gap: f64,
+    pub blink_count: usize,
+    /// Blinks per minute
+    pub blink_rate: f64,
+}
+
+impl TrialQuality {
+    pub fn from_trial(trial: &Trial, meta: &MetaData) -> Self {
+        let times: Vec<f64> = trial
+            .samples
+            .iter()
+            .map(|s| decimal_to_f64(s.time))
+            .collect();
+        let n_samples = times.len();
+
+        let sample_span = match (times.first(), times.last()) {
+            (Some(first), Some(last)) if last > first => Some(last - first),
+            _ => None,
+        };
+        let sampling_rate = sample_span.map(|span| (n_samples - 1) as f64 * 1000. / span);
+        let configured_rate = meta.sampling_rate.map(decimal_to_f64);
+
+        let start = decimal_to_f64(trial.time_record.start);
+        let end = decimal_to_f64(trial.time_record.end);
+        let duration = if end > start {
+            end - start
+        } else {
+            sample_span.unwrap_or_default()
+        };
+
+        // Duration of a single sample, used when measuring gaps
+        let interval = sampling_rate
+            .or(configured_rate)
+            .map_or(0., |rate| 1000. / rate);
+
+        let eyes = if meta.recorded_eyes.is_empty() {
+            [Eye::Left, Eye::Right]
+                .into_iter()
+                .filter(|&eye| trial.samples.iter().any(|s| s.eye(eye).is_some()))
+                .collect()
+        } else {
+            meta.recorded_eyes.clone()
+        };
+
+        let eyes = eyes
+            .into_iter()
+            .map(|eye| EyeQuality::from_trial(trial, eye, &times, duration, interval))
+            .collect();
+
+        TrialQuality {
+            trial_id: trial.id,
+            n_samples,
+            duration,
+            sampling_rate,
+            configured_rate,
+            eyes,
+        }
+    }
+}
+
+impl EyeQuality {
+    fn from_trial(trial: &Trial, eye: Eye, times: &[f64], duration: f64, interval: f64) -> Self {
+        let mut missing = 0;
+        let mut cr_missing = 0;
+        let mut cr_recovering = 0;
+        let mut interpolated = 0;
+        let mut longest_gap: f64 = 0.;
+        let mut gap_start = None;
+
+        for (s, &t) in trial.samples.iter().zip(times) {
+            if s.interpolated {
+                interpolated += 1;
+            }
+            match s.eye(eye) {
+                Some(data) => {
+                    match data.cr {
+                        CRStatus::Missing => cr_missing += 1,
+                        CRStatus::Recovering => cr_recovering += 1,
+                        CRStatus::Found => {}
+                    }
+                    if let Some(from) = gap_start.take() {
+                        longest_gap = longest_gap.max(t - from);
+                    }
+                }
+                None => {
+                    missing += 1;
+                    gap_start.get_or_insert(t);
+                }
+            }
+        }
+        if let (Some(from), Some(last)) = (gap_start, times.last()) {
+            longest_gap = longest_gap.max(last - from + interval);
+        }
+
+        let blink_count = trial
+            .events
+            .iter()
+            .filter(|e| e.eye == eye && matches!(e.info, EventInfo::Blink))
+            .count();
+
+        let proportion = |count: usize| {
+            if times.is_empty() {
+                0.
+            } else {
+                count as f64 / times.len() as f64
+            }
+        };
+
+        EyeQuality {
+            eye,
+            missing_gaze: proportion(missing),
+            cr_missing: proportion(cr_missing),
+            cr_recovering: proportion(cr_recovering),
+            interpolated: proportion(interpolated),
+            longest_gap,
+            blink_count,
+            blink_rate: if duration > 0. {
+                blink_count as f64 * 60_000. / duration
+            } else {
+                0.
+            },
+        }
+    }
+}
+
+impl Trial {
+    pub fn quality(&self, meta: &MetaData) -> TrialQuality {
+        TrialQuality::from_trial(self, meta)
+    }
+}
+
+impl Experiment {
+    pub fn quality_report(&self) -> Vec<TrialQuality> {
+        self.trials
+            .par_iter()
+            .map(|t| TrialQuality::from_trial(t, &self.meta))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::{EventRecord, EyeSampleData, Sample, TimeRecord};
+    use crate::Decimal;
+
+    fn sample(time: i64, cr: Option<CRStatus>) -> Sample {
+        Sample {
+            time: Decimal::from(time),
+            left: cr.map(|cr| EyeSampleData {
+                position: [Decimal::from(100), Decimal::from(100)],
+                area: Decimal::from(1000),
+                velocity: None,
+                cr,
+            }),
+            right: None,
+            resolution: None,
+            interpolated: false,
+        }
+    }
+
+    #[test]
+    fn test_trial_quality() {
+        let mut samples = vec![
+            sample(0, Some(CRStatus::Found)),
+            sample(1, Some(CRStatus::Missing)),
+            sample(2, None),
+            sample(3, None),
+            sample(4, Some(CRStatus::Recovering)),
+            sample(5, Some(CRStatus::Found)),
+            sample(6, Some(CRStatus::Found)),
+            sample(7, None),
+        ];
+        samples[5].interpolated = true;
+        let trial = Trial {
+            samples,
+            events: vec![EventRecord {
+                time_record: TimeRecord {
+                    start: Decimal::from(2),
+                    end: Decimal::from(4),
+                },
+                eye: Eye::Left,
+                resolution: None,
+                info: EventInfo::Blink,
+            }],
+            ..Trial::empty(
+                1,
+                TimeRecord {
+                    start: Decimal::from(0),
+                    end: Decimal::from(1000),
+                },
+            )
+        };
+
+        let quality = trial.quality(&MetaData::default());
+        assert_eq!(quality.n_samples, 8);
+        assert_eq!(quality.duration, 1000.);
+        assert_eq!(quality.sampling_rate, Some(1000.));
+        assert_eq!(quality.configured_rate, None);
+        // Only the left eye has data
+        assert_eq!(quality.eyes.len(), 1);
+
+        let eye = &quality.eyes[0];
+        assert_eq!(eye.eye, Eye::Left);
+        assert_eq!(eye.missing_gaze, 3. / 8.);
+        assert_eq!(eye.cr_missing, 1. / 8.);
+        assert_eq!(eye.cr_recovering, 1. / 8.);
+        assert_eq!(eye.interpolated, 1. / 8.);
+        // Samples 2 and 3 are missing, the trailing gap at 7 lasts one sample
+        assert_eq!(eye.longest_gap, 2.);
+        assert_eq!(eye.blink_count, 1);
+        assert_eq!(eye.blink_rate, 60.);
+    }
+}
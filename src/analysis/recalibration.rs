@@ -0,0 +1,351 @@
+//! Offline recalibration from raw pupil and corneal reflection (CR) samples.
+//!
+//! A bivariate polynomial is fitted from the pupil–CR vector (camera coordinates) to the
+//! known target positions (screen coordinates) of a set of calibration trials. The fitted
+//! mapping can then be applied to the raw samples of any trial to produce new gaze estimates.
+
+use crate::analysis::{least_squares, median};
+use crate::common::Eye;
+use crate::generic::{RawEyeSampleData, Trial};
+use crate::{decimal_to_f64, Decimal};
+use anyhow::anyhow;
+
+#[derive(Debug, Clone)]
+pub struct CalibrationOptions {
+    /// Order of the polynomial mapping. 2 gives the usual biquadratic model.
+    pub order: usize,
+    /// Target to calibrate against. The first target by name is used if unset.
+    pub target: Option<String>,
+    /// Time (ms) after a target position change before samples are used
+    pub onset_delay: f64,
+    /// Length (ms) of the sample window used for each calibration point
+    pub window: f64,
+    /// Minimum number of valid raw samples in the window for a point to be used
+    pub min_samples: usize,
+}
+
+impl Default for CalibrationOptions {
+    fn default() -> Self {
+        CalibrationOptions {
+            order: 2,
+            target: None,
+            onset_delay: 300.,
+            window: 500.,
+            min_samples: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CalibrationPoint {
+    pub trial_id: u32,
+    /// Time of the target onset
+    pub time: f64,
+    /// Target position in screen coordinates
+    pub target: [f64; 2],
+    /// Median pupil–CR vector in the sample window
+    pub pupil_cr: [f64; 2],
+    pub n_samples: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PointResidual {
+    pub point: CalibrationPoint,
+    pub estimate: [f64; 2],
+    pub residual: [f64; 2],
+    /// Euclidean distance between estimate and target
+    pub error: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recalibration {
+    pub eye: Eye,
+    pub order: usize,
+    offset: [f64; 2],
+    scale: [f64; 2],
+    coefficients: [Vec<f64>; 2],
+}
+
+#[derive(Debug, Clone)]
+pub struct RecalibrationResult {
+    pub mapping: Recalibration,
+    pub residuals: Vec<PointResidual>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecalibratedSample {
+    pub time: Decimal,
+    pub position: Option<[f64; 2]>,
+}
+
+/// Pupil–CR difference vector, or `None` if the pupil or the corneal reflection was not tracked.
+pub fn pupil_cr_vector(data: &RawEyeSampleData) -> Option<[f64; 2]> {
+    let area = decimal_to_f64(data.pupil_area);
+    let cr_area = decimal_to_f64(data.cr_area);
+    let v = [
+        decimal_to_f64(data.pupil_position[0]) - decimal_to_f64(data.cr_position[0]),
+        decimal_to_f64(data.pupil_position[1]) - decimal_to_f64(data.cr_position[1]),
+    ];
+    (area > 0. && cr_area > 0. && v.iter().all(|c| c.is_finite())).then_some(v)
+}
+
+/// Extracts one calibration point per target position from the raw samples of a trial.
+pub fn calibration_points(
+    trial: &Trial,
+    eye: Eye,
+    options: &CalibrationOptions,
+) -> Vec<CalibrationPoint> {
+    let target = match &options.target {
+        Some(name) => trial.targets.get(name),
+        None => trial
+            .targets
+            .iter()
+            .min_by(|a, b| a.0.cmp(b.0))
+            .map(|(_, t)| t),
+    };
+    let Some(target) = target else {
+        return Vec::new();
+    };
+
+    let mut onsets: Vec<(f64, [i32; 2])> = target
+        .iter()
+        .map(|t| (decimal_to_f64(t.time), t.position))
+        .collect();
+    onsets.sort_by(|a, b| a.0.total_cmp(&b.0));
+    onsets.dedup_by(|next, prev| next.1 == prev.1);
+
+    let trial_end = decimal_to_f64(trial.time_record.end);
+    let trial_end = if trial_end > 0. {
+        trial_end
+    } else {
+        f64::INFINITY
+    };
+
+    let mut points = Vec::new();
+    for (i, &(onset, position)) in onsets.iter().enumerate() {
+        let segment_end = onsets.get(i + 1).map_or(trial_end, |next| next.0);
+        let from = onset + options.onset_delay;
+        let to = (from + options.window).min(segment_end);
+
+        let (xs, ys): (Vec<f64>, Vec<f64>) = trial
+            .raw_samples
+            .iter()
+            .filter(|s| {
+                let t = decimal_to_f64(s.time);
+                t >= from && t < to
+            })
+            .filter_map(|s| pupil_cr_vector(s.eye(eye)))
+            .map(|v| (v[0], v[1]))
+            .unzip();
+
+        if xs.len() < options.min_samples.max(1) {
+            continue;
+        }
+
+        points.push(CalibrationPoint {
+            trial_id: trial.id,
+            time: onset,
+            target: [position[0] as f64, position[1] as f64],
+            pupil_cr: [median(&xs).unwrap(), median(&ys).unwrap()],
+            n_samples: xs.len(),
+        });
+    }
+    points
+}
+
+fn n_terms(order: usize) -> usize {
+    (order + 1) * (order + 2) / 2
+}
+
+fn polynomial_terms(order: usize, u: f64, v: f64) -> Vec<f64> {
+    let mut terms = Vec::with_capacity(n_terms(order));
+    for degree in 0..=order {
+        for j in 0..=degree {
+            terms.push(u.powi((degree - j) as i32) * v.powi(j as i32));
+        }
+    }
+    terms
+}
+
+impl Recalibration {
+    /// Fits a mapping from the calibration points found in the given trials.
+    pub fn fit<'a, I>(
+        trials: I,
+        eye: Eye,
+        options: &CalibrationOptions,
+    ) -> anyhow::Result<RecalibrationResult>
+    where
+        I: IntoIterator<Item = &'a Trial>,
+    {
+        let points: Vec<CalibrationPoint> = trials
+            .into_iter()
+            .flat_map(|t| calibration_points(t, eye, options))
+            .collect();
+        Self::fit_points(&points, eye, options.order)
+    }
+
+    /// Fits a mapping from already extracted calibration points.
+    pub fn fit_points(
+        points: &[CalibrationPoint],
+        eye: Eye,
+        order: usize,
+    ) -> anyhow::Result<RecalibrationResult> {
+        let required = n_terms(order);
+        if points.len() < required {
+            return Err(anyhow!(
+                "A polynomial of order {order} needs at least {required} calibration points, found {}",
+                points.len()
+            ));
+        }
+
+        // Normalise the input vectors to keep the normal equations well conditioned
+        let mut offset = [0.; 2];
+        let mut scale = [1.; 2];
+        for c in 0..2 {
+            let values: Vec<f64> = points.iter().map(|p| p.pupil_cr[c]).collect();
+            let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            offset[c] = (min + max) / 2.;
+            if max > min {
+                scale[c] = (max - min) / 2.;
+            }
+        }
+
+        let rows: Vec<Vec<f64>> = points
+            .iter()
+            .map(|p| {
+                polynomial_terms(
+                    order,
+                    (p.pupil_cr[0] - offset[0]) / scale[0],
+                    (p.pupil_cr[1] - offset[1]) / scale[1],
+                )
+            })
+            .collect();
+
+        let mut coefficients = [Vec::new(), Vec::new()];
+        for (c, coef) in coefficients.iter_mut().enumerate() {
+            let targets: Vec<f64> = points.iter().map(|p| p.target[c]).collect();
+            *coef = least_squares(&rows, &targets).ok_or_else(|| {
+                anyhow!("Calibration points are degenerate, could not fit mapping")
+            })?;
+        }
+
+        let mapping = Recalibration {
+            eye,
+            order,
+            offset,
+            scale,
+            coefficients,
+        };
+
+        let residuals = points
+            .iter()
+            .map(|p| {
+                let estimate = mapping.map_vector(p.pupil_cr);
+                let residual = [estimate[0] - p.target[0], estimate[1] - p.target[1]];
+                PointResidual {
+                    point: *p,
+                    estimate,
+                    residual,
+                    error: residual[0].hypot(residual[1]),
+                }
+            })
+            .collect();
+
+        Ok(RecalibrationResult { mapping, residuals })
+    }
+
+    /// Maps a pupil–CR vector to screen coordinates.
+    pub fn map_vector(&self, pupil_cr: [f64; 2]) -> [f64; 2] {
+        let terms = polynomial_terms(
+            self.order,
+            (pupil_cr[0] - self.offset[0]) / self.scale[0],
+            (pupil_cr[1] - self.offset[1]) / self.scale[1],
+        );
+        let eval = |coef: &[f64]| terms.iter().zip(coef).map(|(t, c)| t * c).sum();
+        [eval(&self.coefficients[0]), eval(&self.coefficients[1])]
+    }
+
+    pub fn apply(&self, data: &RawEyeSampleData) -> Option<[f64; 2]> {
+        pupil_cr_vector(data).map(|v| self.map_vector(v))
+    }
+
+    /// Produces new gaze estimates for every raw sample of the trial.
+    pub fn apply_trial(&self, trial: &Trial) -> Vec<RecalibratedSample> {
+        trial
+            .raw_samples
+            .iter()
+            .map(|s| RecalibratedSample {
+                time: s.time,
+                position: self.apply(s.eye(self.eye)),
+            })
+            .collect()
+    }
+}
+
+impl RecalibrationResult {
+    pub fn rms_error(&self) -> f64 {
+        let sum: f64 = self.residuals.iter().map(|r| r.error * r.error).sum();
+        (sum / self.residuals.len() as f64).sqrt()
+    }
+
+    pub fn max_error(&self) -> f64 {
+        self.residuals.iter().map(|r| r.error).fold(0., f64::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_recovers_quadratic_mapping() {
+        let truth = |v: [f64; 2]| {
+            [
+                960. + 12. * v[0] + 0.05 * v[0] * v[0] - 0.02 * v[0] * v[1],
+                540. - 9. * v[1] + 0.03 * v[1] * v[1],
+            ]
+        };
+
+        let mut points = Vec::new();
+        for i in -2..=2 {
+            for j in -2..=2 {
+                let pupil_cr = [i as f64 * 20., j as f64 * 15.];
+                points.push(CalibrationPoint {
+                    trial_id: 0,
+                    time: 0.,
+                    target: truth(pupil_cr),
+                    pupil_cr,
+                    n_samples: 1,
+                });
+            }
+        }
+
+        let res = Recalibration::fit_points(&points, Eye::Left, 2).unwrap();
+        assert!(res.max_error() < 1e-6);
+
+        let estimate = res.mapping.map_vector([7., -3.]);
+        let expected = truth([7., -3.]);
+        assert!((estimate[0] - expected[0]).abs() < 1e-6);
+        assert!((estimate[1] - expected[1]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_pupil_cr_vector() {
+        let mut data = RawEyeSampleData {
+            pupil_position: [Decimal::from(120), Decimal::from(80)],
+            pupil_area: Decimal::from(900),
+            pupil_size: [Decimal::from(30), Decimal::from(30)],
+            cr_position: [Decimal::from(100), Decimal::from(90)],
+            cr_area: Decimal::from(40),
+        };
+        assert_eq!(pupil_cr_vector(&data), Some([20., -10.]));
+
+        // The corneal reflection is lost, its position is stale
+        data.cr_area = Decimal::from(0);
+        assert_eq!(pupil_cr_vector(&data), None);
+        data.cr_area = Decimal::from(40);
+        data.pupil_area = Decimal::from(0);
+        assert_eq!(pupil_cr_vector(&data), None);
+    }
+}
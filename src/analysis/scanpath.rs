@@ -0,0 +1,460 @@
+//! Scanpath construction and comparison.
+//!
+//! Scanpaths are built from the fixation events of a trial and compared with string edit
+//! distance, ScanMatch-style Needleman–Wunsch alignment and the MultiMatch measures
+//! (without the optional simplification step).
+
+use crate::common::Eye;
+use crate::generic::{Experiment, Trial};
+use rayon::prelude::*;
+use std::f64::consts::PI;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fixation {
+    /// Start time in ms
+    pub start: f64,
+    /// Duration in ms
+    pub duration: f64,
+    pub position: [f64; 2],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scanpath {
+    pub trial_id: u32,
+    pub eye: Eye,
+    pub fixations: Vec<Fixation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterestArea {
+    pub label: String,
+    /// left, top, right, bottom in screen coordinates
+    pub bounds: [f64; 4],
+}
+
+/// Assigns fixations to discrete regions for the string based measures.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Labeling {
+    /// Regular grid over `bounds` (left, top, right, bottom). A grid without columns or rows
+    /// labels no fixation
+    Grid {
+        bounds: [f64; 4],
+        columns: usize,
+        rows: usize,
+    },
+    /// Fixations outside every area are left out of the label sequence
+    InterestAreas(Vec<InterestArea>),
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanMatchOptions {
+    /// Fixations are repeated once per started bin of this many ms. Zero disables
+    /// temporal binning.
+    pub temporal_bin: f64,
+    /// Region centers further apart than this are scored negatively
+    pub threshold: f64,
+    /// Score added for every gap, usually zero or negative
+    pub gap_penalty: f64,
+}
+
+impl Default for ScanMatchOptions {
+    fn default() -> Self {
+        ScanMatchOptions {
+            temporal_bin: 50.,
+            threshold: 200.,
+            gap_penalty: 0.,
+        }
+    }
+}
+
+/// MultiMatch similarities, each in the range 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MultiMatch {
+    pub vector: f64,
+    pub direction: f64,
+    pub length: f64,
+    pub position: f64,
+    pub duration: f64,
+}
+
+impl InterestArea {
+    pub fn contains(&self, p: [f64; 2]) -> bool {
+        p[0] >= self.bounds[0]
+            && p[0] < self.bounds[2]
+            && p[1] >= self.bounds[1]
+            && p[1] < self.bounds[3]
+    }
+
+    pub fn center(&self) -> [f64; 2] {
+        [
+            (self.bounds[0] + self.bounds[2]) / 2.,
+            (self.bounds[1] + self.bounds[3]) / 2.,
+        ]
+    }
+}
+
+impl Labeling {
+    pub fn label(&self, p: [f64; 2]) -> Option<usize> {
+        match self {
+            Labeling::Grid {
+                bounds,
+                columns,
+                rows,
+            } => {
+                if *columns == 0 || *rows == 0 {
+                    return None;
+                }
+                let u = (p[0] - bounds[0]) / (bounds[2] - bounds[0]);
+                let v = (p[1] - bounds[1]) / (bounds[3] - bounds[1]);
+                if !(0. ..1.).contains(&u) || !(0. ..1.).contains(&v) {
+                    return None;
+                }
+                let col = (u * *columns as f64) as usize;
+                let row = (v * *rows as f64) as usize;
+                Some(row * columns + col)
+            }
+            Labeling::InterestAreas(areas) => areas.iter().position(|a| a.contains(p)),
+        }
+    }
+
+    pub fn center(&self, label: usize) -> [f64; 2] {
+        match self {
+            Labeling::Grid {
+                bounds,
+                columns,
+                rows,
+            } => {
+                let w = (bounds[2] - bounds[0]) / *columns as f64;
+                let h = (bounds[3] - bounds[1]) / *rows as f64;
+                [
+                    bounds[0] + ((label % columns) as f64 + 0.5) * w,
+                    bounds[1] + ((label / columns) as f64 + 0.5) * h,
+                ]
+            }
+            Labeling::InterestAreas(areas) => areas[label].center(),
+        }
+    }
+
+    /// Readable name of a label, `A1`, `B3`, ... for grids. Rows after `Z` continue with
+    /// `AA`, `AB`, ... as spreadsheet columns do.
+    pub fn name(&self, label: usize) -> String {
+        match self {
+            Labeling::Grid { columns, .. } => {
+                let mut row = label / columns + 1;
+                let mut letters = Vec::new();
+                while row > 0 {
+                    row -= 1;
+                    letters.push((b'A' + (row % 26) as u8) as char);
+                    row /= 26;
+                }
+                let row: String = letters.into_iter().rev().collect();
+                format!("{row}{}", label % columns + 1)
+            }
+            Labeling::InterestAreas(areas) => areas[label].label.clone(),
+        }
+    }
+}
+
+impl Scanpath {
+    pub fn from_trial(trial: &Trial, eye: Eye) -> Self {
+        let fixations = trial
+            .events
+            .iter()
+            .filter(|e| e.eye == eye)
+            .filter_map(|e| {
+                Some(Fixation {
+                    start: crate::decimal_to_f64(e.time_record.start),
+                    duration: e.duration(),
+                    position: e.fixation_position()?,
+                })
+            })
+            .collect();
+        Scanpath {
+            trial_id: trial.id,
+            eye,
+            fixations,
+        }
+    }
+
+    pub fn labels(&self, labeling: &Labeling) -> Vec<usize> {
+        self.fixations
+            .iter()
+            .filter_map(|f| labeling.label(f.position))
+            .collect()
+    }
+
+    /// Label sequence with every fixation repeated once per started `bin` ms.
+    fn binned_labels(&self, labeling: &Labeling, bin: f64) -> Vec<usize> {
+        self.fixations
+            .iter()
+            .filter_map(|f| {
+                let label = labeling.label(f.position)?;
+                let n = if bin > 0. {
+                    (f.duration / bin).ceil().max(1.) as usize
+                } else {
+                    1
+                };
+                Some(std::iter::repeat(label).take(n))
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Saccade vectors between consecutive fixations.
+    fn vectors(&self) -> Vec<[f64; 2]> {
+        self.fixations
+            .windows(2)
+            .map(|w| {
+                [
+                    w[1].position[0] - w[0].position[0],
+                    w[1].position[1] - w[0].position[1],
+                ]
+            })
+            .collect()
+    }
+}
+
+pub fn levenshtein<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, x) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, y) in b.iter().enumerate() {
+            let substitution = prev[j] + usize::from(x != y);
+            current[j + 1] = substitution.min(prev[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut current);
+    }
+    prev[b.len()]
+}
+
+/// Edit distance similarity, `1 - distance / longest sequence`.
+pub fn edit_similarity(a: &Scanpath, b: &Scanpath, labeling: &Labeling) -> f64 {
+    let (la, lb) = (a.labels(labeling), b.labels(labeling));
+    let longest = la.len().max(lb.len());
+    if longest == 0 {
+        return 1.;
+    }
+    1. - levenshtein(&la, &lb) as f64 / longest as f64
+}
+
+/// ScanMatch similarity: Needleman–Wunsch alignment of temporally binned label sequences,
+/// normalised by the best possible score of the longer sequence.
+pub fn scanmatch(
+    a: &Scanpath,
+    b: &Scanpath,
+    labeling: &Labeling,
+    options: &ScanMatchOptions,
+) -> f64 {
+    let la = a.binned_labels(labeling, options.temporal_bin);
+    let lb = b.binned_labels(labeling, options.temporal_bin);
+    let longest = la.len().max(lb.len());
+    if longest == 0 {
+        return 1.;
+    }
+
+    let substitution = |x: usize, y: usize| {
+        let (cx, cy) = (labeling.center(x), labeling.center(y));
+        1. - (cx[0] - cy[0]).hypot(cx[1] - cy[1]) / options.threshold
+    };
+
+    let gap = options.gap_penalty;
+    let mut score = vec![vec![0.; lb.len() + 1]; la.len() + 1];
+    for (i, row) in score.iter_mut().enumerate() {
+        row[0] = gap * i as f64;
+    }
+    for (j, s) in score[0].iter_mut().enumerate() {
+        *s = gap * j as f64;
+    }
+    for i in 1..=la.len() {
+        for j in 1..=lb.len() {
+            let diagonal = score[i - 1][j - 1] + substitution(la[i - 1], lb[j - 1]);
+            score[i][j] = diagonal
+                .max(score[i - 1][j] + gap)
+                .max(score[i][j - 1] + gap);
+        }
+    }
+
+    (score[la.len()][lb.len()] / longest as f64).clamp(0., 1.)
+}
+
+/// MultiMatch similarities. `screen` is the width and height used to normalise distances.
+///
+/// Returns `None` if either scanpath has fewer than two fixations.
+pub fn multimatch(a: &Scanpath, b: &Scanpath, screen: [f64; 2]) -> Option<MultiMatch> {
+    let (va, vb) = (a.vectors(), b.vectors());
+    if va.is_empty() || vb.is_empty() {
+        return None;
+    }
+
+    // Align the saccade vectors along the cheapest monotone path through the matrix of
+    // vector differences
+    let cost = |i: usize, j: usize| (va[i][0] - vb[j][0]).hypot(va[i][1] - vb[j][1]);
+    let (n, m) = (va.len(), vb.len());
+    let mut acc = vec![vec![f64::INFINITY; m]; n];
+    for i in 0..n {
+        for j in 0..m {
+            let best = if i == 0 && j == 0 {
+                0.
+            } else {
+                let up = if i > 0 { acc[i - 1][j] } else { f64::INFINITY };
+                let left = if j > 0 { acc[i][j - 1] } else { f64::INFINITY };
+                let diagonal = if i > 0 && j > 0 {
+                    acc[i - 1][j - 1]
+                } else {
+                    f64::INFINITY
+                };
+                up.min(left).min(diagonal)
+            };
+            acc[i][j] = best + cost(i, j);
+        }
+    }
+
+    let mut path = vec![(n - 1, m - 1)];
+    let (mut i, mut j) = (n - 1, m - 1);
+    while i > 0 || j > 0 {
+        let mut candidates = Vec::new();
+        if i > 0 && j > 0 {
+            candidates.push((i - 1, j - 1));
+        }
+        if i > 0 {
+            candidates.push((i - 1, j));
+        }
+        if j > 0 {
+            candidates.push((i, j - 1));
+        }
+        (i, j) = candidates
+            .into_iter()
+            .min_by(|x, y| acc[x.0][x.1].total_cmp(&acc[y.0][y.1]))
+            .unwrap();
+        path.push((i, j));
+    }
+    path.reverse();
+
+    let diagonal = screen[0].hypot(screen[1]);
+    let length = |v: [f64; 2]| v[0].hypot(v[1]);
+    let k = path.len() as f64;
+    let mut sums = [0.; 5];
+    for &(i, j) in &path {
+        let (u, v) = (va[i], vb[j]);
+        let (fa, fb) = (a.fixations[i], b.fixations[j]);
+
+        sums[0] += (u[0] - v[0]).hypot(u[1] - v[1]);
+        let mut angle = (u[1].atan2(u[0]) - v[1].atan2(v[0])).abs();
+        if angle > PI {
+            angle = 2. * PI - angle;
+        }
+        sums[1] += angle;
+        sums[2] += (length(u) - length(v)).abs();
+        sums[3] += (fa.position[0] - fb.position[0]).hypot(fa.position[1] - fb.position[1]);
+        let longest = fa.duration.max(fb.duration);
+        if longest > 0. {
+            sums[4] += (fa.duration - fb.duration).abs() / longest;
+        }
+    }
+
+    Some(MultiMatch {
+        vector: 1. - sums[0] / k / (2. * diagonal),
+        direction: 1. - sums[1] / k / PI,
+        length: 1. - sums[2] / k / diagonal,
+        position: 1. - sums[3] / k / diagonal,
+        duration: 1. - sums[4] / k,
+    })
+}
+
+/// Evaluates `measure` for every pair of scanpaths in parallel.
+pub fn similarity_matrix<T, F>(scanpaths: &[Scanpath], measure: F) -> Vec<Vec<T>>
+where
+    T: Send,
+    F: Fn(&Scanpath, &Scanpath) -> T + Sync,
+{
+    scanpaths
+        .par_iter()
+        .map(|a| scanpaths.iter().map(|b| measure(a, b)).collect())
+        .collect()
+}
+
+impl Experiment {
+    /// Scanpaths of every trial, to be compared within or across experiments.
+    pub fn scanpaths(&self, eye: Eye) -> Vec<Scanpath> {
+        self.trials
+            .iter()
+            .map(|t| Scanpath::from_trial(t, eye))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scanpath(points: &[[f64; 2]]) -> Scanpath {
+        Scanpath {
+            trial_id: 0,
+            eye: Eye::Left,
+            fixations: points
+                .iter()
+                .enumerate()
+                .map(|(i, &position)| Fixation {
+                    start: i as f64 * 300.,
+                    duration: 250.,
+                    position,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_string_measures() {
+        let labeling = Labeling::Grid {
+            bounds: [0., 0., 300., 300.],
+            columns: 3,
+            rows: 3,
+        };
+        let a = scanpath(&[[50., 50.], [150., 50.], [250., 250.]]);
+        let b = scanpath(&[[50., 50.], [250., 250.]]);
+
+        assert_eq!(a.labels(&labeling), vec![0, 1, 8]);
+        assert_eq!(labeling.name(8), "C3");
+        let tall = Labeling::Grid {
+            bounds: [0., 0., 300., 3000.],
+            columns: 2,
+            rows: 60,
+        };
+        assert_eq!(tall.name(51), "Z2");
+        assert_eq!(tall.name(52), "AA1");
+        assert_eq!(tall.name(119), "BH2");
+        let empty = Labeling::Grid {
+            bounds: [0., 0., 300., 300.],
+            columns: 0,
+            rows: 3,
+        };
+        assert!(a.labels(&empty).is_empty());
+        assert!((edit_similarity(&a, &b, &labeling) - 2. / 3.).abs() < 1e-9);
+        assert_eq!(edit_similarity(&a, &a, &labeling), 1.);
+
+        let options = ScanMatchOptions::default();
+        assert!((scanmatch(&a, &a, &labeling, &options) - 1.).abs() < 1e-9);
+        assert!(scanmatch(&a, &b, &labeling, &options) < 1.);
+    }
+
+    #[test]
+    fn test_multimatch_identical() {
+        let a = scanpath(&[[100., 100.], [400., 120.], [380., 500.], [90., 300.]]);
+        let mm = multimatch(&a, &a, [1920., 1080.]).unwrap();
+        assert_eq!(
+            mm,
+            MultiMatch {
+                vector: 1.,
+                direction: 1.,
+                length: 1.,
+                position: 1.,
+                duration: 1.,
+            }
+        );
+
+        let b = scanpath(&[[100., 100.], [400., 500.]]);
+        let mm = multimatch(&a, &b, [1920., 1080.]).unwrap();
+        assert!(mm.vector < 1. && mm.direction < 1.);
+    }
+}
@@ -0,0 +1,321 @@
+//! De-identification of experiments before sharing.
+//!
+//! [`Experiment::anonymize`] removes or shifts the recording date, reduces paths in the preamble
+//! (e.g. `CONVERTED FROM C:\Users\<name>\...`) to file names, applies redaction rules to
+//! messages and trial variables and can re-base all tracker timestamps to zero. It returns a log
+//! of the changes, which names what was changed but never repeats the removed content.
+
+use crate::decimal_to_f64;
+use crate::generic::Experiment;
+use crate::{Decimal, NaiveDateTime};
+use anyhow::anyhow;
+use regex::Regex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DateHandling {
+    #[default]
+    Keep,
+    /// Replace the recording date with the Unix epoch
+    Strip,
+    /// Shift the recording date by a number of days, e.g. a random offset per participant
+    ShiftDays(i64),
+}
+
+#[derive(Debug, Clone)]
+pub struct RedactionRule {
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+impl RedactionRule {
+    pub fn new(pattern: &str, replacement: &str) -> anyhow::Result<Self> {
+        Ok(RedactionRule {
+            pattern: Regex::new(pattern)?,
+            replacement: replacement.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AnonymizeOptions {
+    pub dates: DateHandling,
+    /// Reduce paths in the preamble to file names
+    pub scrub_paths: bool,
+    /// Rules applied to messages and trial variables
+    pub rules: Vec<RedactionRule>,
+    /// Subtract the time of the first trial or sample from all tracker timestamps
+    pub rebase_time: bool,
+}
+
+fn shift_error(days: i64) -> anyhow::Error {
+    anyhow!("Shifting the recording date by {days} days is out of range")
+}
+
+// In milliseconds, as `Duration::days` panics for large values
+#[cfg(feature = "py-ext")]
+fn shift_days(date: NaiveDateTime, days: i64) -> anyhow::Result<NaiveDateTime> {
+    days.checked_mul(86_400_000)
+        .and_then(|ms| {
+            date.0
+                .checked_add_signed(chrono::Duration::milliseconds(ms))
+        })
+        .map(NaiveDateTime)
+        .ok_or_else(|| shift_error(days))
+}
+
+#[cfg(not(feature = "py-ext"))]
+fn shift_days(date: NaiveDateTime, days: i64) -> anyhow::Result<NaiveDateTime> {
+    days.checked_mul(86_400_000)
+        .and_then(|ms| date.checked_add_signed(chrono::Duration::milliseconds(ms)))
+        .ok_or_else(|| shift_error(days))
+}
+
+/// Applies `rules` to `text`, returning the number of replaced matches.
+fn redact(text: &mut String, rules: &[RedactionRule]) -> usize {
+    let mut count = 0;
+    for rule in rules {
+        let matches = rule.pattern.find_iter(text).count();
+        if matches > 0 {
+            *text = rule
+                .pattern
+                .replace_all(text, rule.replacement.as_str())
+                .into_owned();
+            count += matches;
+        }
+    }
+    count
+}
+
+impl Experiment {
+    /// Earliest trial start or sample time.
+    fn first_time(&self) -> Option<Decimal> {
+        self.trials
+            .iter()
+            .flat_map(|t| [Some(t.time_record.start), t.samples.first().map(|s| s.time)])
+            .flatten()
+            .min_by(|a, b| decimal_to_f64(*a).total_cmp(&decimal_to_f64(*b)))
+    }
+
+    /// Removes identifying information according to `options` and returns a log of the changes.
+    /// Fails without changing the experiment if the shifted recording date is out of range.
+    pub fn anonymize(&mut self, options: &AnonymizeOptions) -> anyhow::Result<Vec<String>> {
+        let mut log = Vec::new();
+
+        match options.dates {
+            DateHandling::Keep => {}
+            DateHandling::Strip => {
+                self.meta.recording_datetime = NaiveDateTime::default();
+                log.push("recording date removed".to_string());
+            }
+            DateHandling::ShiftDays(days) => {
+                self.meta.recording_datetime = shift_days(self.meta.recording_datetime, days)?;
+                log.push(format!("recording date shifted by {days} days"));
+            }
+        }
+
+        // The source of a conversion is everything between `FROM` and `using`, spaces included
+        let converted = Regex::new(r"CONVERTED FROM (.+?) using ").unwrap();
+        // Other paths start a token and have a drive letter or at least two segments, so text
+        // like `EyeLink 1000/Plus` is kept. Folders of drive letter paths may contain spaces,
+        // e.g. `C:\Users\Jane Doe\`, so these extend up to the last separator.
+        let path = Regex::new(
+            r#"(^|[\s"'])(?:[A-Za-z]:[\\/](?:[^\\/"]+[\\/])*|(?:[\\/][^\\/\s"]+)+[\\/])([^\\/\s"]*)"#,
+        )
+        .unwrap();
+        for (i, line) in self.meta.preamble_lines.iter_mut().enumerate() {
+            if options.scrub_paths {
+                let scrubbed = match converted.captures(line) {
+                    Some(captures) => {
+                        let source = captures.get(1).unwrap();
+                        let name = source
+                            .as_str()
+                            .rsplit(['\\', '/'])
+                            .next()
+                            .unwrap_or_default();
+                        (name.len() < source.len()).then(|| {
+                            format!("{}{name}{}", &line[..source.start()], &line[source.end()..])
+                        })
+                    }
+                    None => path
+                        .is_match(line)
+                        .then(|| path.replace_all(line, "$1$2").into_owned()),
+                };
+                if let Some(scrubbed) = scrubbed {
+                    *line = scrubbed;
+                    log.push(format!(
+                        "preamble line {}: path reduced to file name",
+                        i + 1
+                    ));
+                }
+            }
+            // The conversion line ends with the date of the conversion
+            if options.dates != DateHandling::Keep && line.contains("CONVERTED FROM") {
+                if let Some(end) = line.rfind(" on ") {
+                    line.truncate(end);
+                    log.push(format!("preamble line {}: conversion date removed", i + 1));
+                }
+            }
+        }
+
+        for trial in &mut self.trials {
+            for msg in &mut trial.messages {
+                let count = redact(&mut msg.text, &options.rules);
+                if count > 0 {
+                    log.push(format!(
+                        "trial {}: {count} matches redacted in message at {}",
+                        trial.id, msg.time
+                    ));
+                }
+            }
+            for (label, value) in self.variable_labels.iter().zip(&mut trial.variables) {
+                let count = redact(value, &options.rules);
+                if count > 0 {
+                    log.push(format!(
+                        "trial {}: {count} matches redacted in variable {label}",
+                        trial.id
+                    ));
+                }
+            }
+        }
+
+        if options.rebase_time {
+            if let Some(offset) = self.first_time() {
+                self.shift_time(offset);
+                log.push(format!("timestamps re-based, {offset} ms subtracted"));
+            }
+        }
+        Ok(log)
+    }
+
+    /// Subtracts `offset` from all tracker timestamps.
+    // The Decimal of the Python bindings implements `Sub` but not `SubAssign`
+    #[allow(clippy::assign_op_pattern)]
+    fn shift_time(&mut self, offset: Decimal) {
+        for trial in &mut self.trials {
+            let record = &mut trial.time_record;
+            record.start = record.start - offset;
+            record.end = record.end - offset;
+            for s in &mut trial.samples {
+                s.time = s.time - offset;
+            }
+            for s in &mut trial.raw_samples {
+                s.time = s.time - offset;
+            }
+            for e in &mut trial.events {
+                e.time_record.start = e.time_record.start - offset;
+                e.time_record.end = e.time_record.end - offset;
+            }
+            for f in &mut trial.camera_frames {
+                f.eyelink_time = f.eyelink_time.map(|t| t - offset);
+            }
+            for m in &mut trial.messages {
+                m.time = m.time - offset;
+            }
+            for t in trial.targets.values_mut().flatten() {
+                t.time = t.time - offset;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::{Message, MetaData, TimeRecord, Trial};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_anonymize() {
+        let time = |t: &str| Decimal::from_str(t).unwrap();
+        let mut exp = Experiment {
+            meta: MetaData {
+                preamble_lines: vec![
+                    r"CONVERTED FROM C:\Users\jdoe\study\P01.edf using edfapi 4.2 on Wed Jun 14 10:00:00 2023".to_string(),
+                    "TYPE: EDF_FILE BINARY EVENT SAMPLE TAGGED".to_string(),
+                    "SOURCE: EyeLink 1000/Plus, script /home/jdoe/study/run.py".to_string(),
+                ],
+                ..Default::default()
+            },
+            variable_labels: vec!["name".to_string()],
+            trials: vec![Trial {
+                variables: vec!["Jane Doe".to_string()],
+                messages: vec![Message {
+                    time: time("1500"),
+                    text: "participant Jane Doe ready".to_string(),
+                }],
+                ..Trial::empty(
+                    1,
+                    TimeRecord {
+                        start: time("1000"),
+                        end: time("2000"),
+                    },
+                )
+            }],
+        };
+
+        let shifted = exp.anonymize(&AnonymizeOptions {
+            dates: DateHandling::ShiftDays(i64::MAX),
+            ..Default::default()
+        });
+        assert!(shifted.is_err());
+
+        let log = exp
+            .anonymize(&AnonymizeOptions {
+                dates: DateHandling::Strip,
+                scrub_paths: true,
+                rules: vec![RedactionRule::new("Jane Doe", "[name]").unwrap()],
+                rebase_time: true,
+            })
+            .unwrap();
+        assert_eq!(
+            exp.meta.preamble_lines[0],
+            "CONVERTED FROM P01.edf using edfapi 4.2"
+        );
+        assert_eq!(
+            exp.meta.preamble_lines[1],
+            "TYPE: EDF_FILE BINARY EVENT SAMPLE TAGGED"
+        );
+        assert_eq!(
+            exp.meta.preamble_lines[2],
+            "SOURCE: EyeLink 1000/Plus, script run.py"
+        );
+        let trial = &exp.trials[0];
+        assert_eq!(trial.messages[0].text, "participant [name] ready");
+        assert_eq!(trial.variables, ["[name]"]);
+        assert_eq!(trial.messages[0].time, time("500"));
+        assert_eq!(trial.time_record.start, time("0"));
+        assert_eq!(log.len(), 7);
+        assert!(log
+            .iter()
+            .all(|l| !l.contains("Jane") && !l.contains("jdoe")));
+    }
+
+    #[test]
+    fn test_paths_with_spaces() {
+        let mut exp = Experiment {
+            meta: MetaData {
+                preamble_lines: vec![
+                    r"CONVERTED FROM C:\Users\Jane Doe\study\P01 final.edf using edfapi 4.2 on Wed Jun 14 10:00:00 2023".to_string(),
+                    r"SCRIPT: C:\Users\Jane Doe\study\run.py".to_string(),
+                ],
+                ..Default::default()
+            },
+            variable_labels: Vec::new(),
+            trials: Vec::new(),
+        };
+        let log = exp
+            .anonymize(&AnonymizeOptions {
+                scrub_paths: true,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            exp.meta.preamble_lines,
+            [
+                "CONVERTED FROM P01 final.edf using edfapi 4.2 on Wed Jun 14 10:00:00 2023",
+                "SCRIPT: run.py"
+            ]
+        );
+        assert_eq!(log.len(), 2);
+    }
+}
@@ -12,6 +12,10 @@ use std::str::FromStr;
 #[cfg(feature = "py-ext")]
 use pyo3::prelude::*;
 
+/// Version of the parser and of the conversion to [`crate::generic`], part of the key of the
+/// [parse cache](crate::cache). Bump it when the same input parses to a different experiment.
+pub const PARSER_VERSION: u32 = 1;
+
 #[derive(Debug, Clone)]
 pub enum Element {
     Preamble(PreambleMsg),
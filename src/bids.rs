@@ -0,0 +1,386 @@
+//! Export to the BIDS eye-tracking layout.
+//!
+//! Each recorded eye is written as a separate `*_recording-eye<N>_physio.tsv.gz` file with a
+//! JSON sidecar, following the eye-tracking extension of the BIDS specification. Trials and
+//! messages are written to a `*_events.tsv` file, with one column per trial variable.
+//!
+//! Onsets in the events file are in seconds since the first sample of the recording, physio
+//! timestamps are kept in the tracker clock (ms).
+
+use crate::common::Eye;
+use crate::decimal_to_f64;
+use crate::generic::{Experiment, Trial};
+use crate::Decimal;
+use anyhow::anyhow;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::{json, Value};
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct BidsOptions {
+    pub subject: String,
+    pub session: Option<String>,
+    pub task: String,
+    pub run: Option<u32>,
+    /// BIDS datatype directory, `beh` unless recorded together with imaging data
+    pub datatype: String,
+    /// Sampling rate in Hz, for recordings without a recording configuration (`RECCFG`)
+    pub sampling_rate: Option<f64>,
+}
+
+impl BidsOptions {
+    pub fn new(subject: &str, task: &str) -> Self {
+        BidsOptions {
+            subject: subject.to_string(),
+            session: None,
+            task: task.to_string(),
+            run: None,
+            datatype: "beh".to_string(),
+            sampling_rate: None,
+        }
+    }
+
+    /// Directory of the recording, relative to the dataset root.
+    fn directory(&self) -> PathBuf {
+        let mut dir = PathBuf::from(format!("sub-{}", self.subject));
+        if let Some(ses) = &self.session {
+            dir.push(format!("ses-{ses}"));
+        }
+        dir.join(&self.datatype)
+    }
+
+    /// File name prefix of the recording, `sub-<label>[_ses-<label>]_task-<label>[_run-<index>]`.
+    fn prefix(&self) -> String {
+        let mut prefix = format!("sub-{}", self.subject);
+        if let Some(ses) = &self.session {
+            prefix.push_str(&format!("_ses-{ses}"));
+        }
+        prefix.push_str(&format!("_task-{}", self.task));
+        if let Some(run) = self.run {
+            prefix.push_str(&format!("_run-{run}"));
+        }
+        prefix
+    }
+}
+
+fn na<T: Display>(value: Option<T>) -> String {
+    value.map_or_else(|| "n/a".to_string(), |v| v.to_string())
+}
+
+/// Values in the events file may not contain tabs or newlines.
+fn tsv_value(value: &str) -> String {
+    if value.is_empty() {
+        "n/a".to_string()
+    } else {
+        value.replace(['\t', '\n', '\r'], " ")
+    }
+}
+
+fn write_json(path: &Path, value: &Value) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, value)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+impl Experiment {
+    /// Time of the first sample, the reference for the event onsets.
+    fn first_sample_time(&self) -> Option<Decimal> {
+        self.trials
+            .iter()
+            .find_map(|t| t.samples.first().map(|s| s.time))
+    }
+
+    fn physio_sidecar(&self, eye: Eye, sampling_rate: f64) -> Value {
+        let meta = &self.meta;
+        let model = meta.preamble_lines.iter().find_map(|l| {
+            l.trim()
+                .strip_prefix("VERSION:")
+                .map(|v| v.trim().to_string())
+        });
+        let software = meta
+            .preamble_lines
+            .iter()
+            .find(|l| l.trim().starts_with("EYELINK"))
+            .map(|l| l.trim().to_string());
+
+        let mut sidecar = json!({
+            "PhysioType": "eyetrack",
+            "SamplingFrequency": sampling_rate,
+            "StartTime": 0,
+            "Columns": ["timestamp", "x_coordinate", "y_coordinate", "pupil_size"],
+            "timestamp": {
+                "Description": "Timestamp in the tracker clock",
+                "Units": "ms",
+            },
+            "x_coordinate": {
+                "Description": "Gaze position, horizontal",
+                "Units": "pixel",
+            },
+            "y_coordinate": {
+                "Description": "Gaze position, vertical",
+                "Units": "pixel",
+            },
+            "pupil_size": {
+                "Description": "Pupil area or diameter, as configured on the tracker",
+                "Units": "arbitrary",
+            },
+            "RecordedEye": eye.name(),
+            "SampleCoordinateSystem": "gaze-on-screen",
+            "Manufacturer": "SR-Research",
+            "ManufacturersModelName": model,
+            "SoftwareVersions": software,
+            "CalibrationType": meta.calibration_type,
+            "EyeTrackingMethod": meta.tracking_mode.as_deref().map(|m| match m {
+                "CR" => "P-CR",
+                _ => "P",
+            }),
+            "PupilFitMethod": meta.pupil_fit_method,
+        });
+
+        if let Some([left, top, right, bottom]) = meta.gaze_coords.map(|c| c.map(decimal_to_f64)) {
+            sidecar["ScreenResolution"] = json!([right - left + 1., bottom - top + 1.]);
+        }
+        // Unknown recommended fields are left out rather than written as null
+        if let Value::Object(map) = &mut sidecar {
+            map.retain(|_, v| !v.is_null());
+        }
+        sidecar
+    }
+
+    fn write_physio(&self, path: &Path, eye: Eye) -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut writer = GzEncoder::new(file, Compression::default());
+        for s in self.trials.iter().flat_map(|t| &t.samples) {
+            let data = s.eye(eye);
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                s.time,
+                na(data.map(|d| d.position[0])),
+                na(data.map(|d| d.position[1])),
+                na(data.map(|d| d.area)),
+            )?;
+        }
+        writer.finish()?.flush()?;
+        Ok(())
+    }
+
+    fn write_events(&self, path: &Path, start: Decimal) -> anyhow::Result<()> {
+        let onset = |time: Decimal| (decimal_to_f64(time) - decimal_to_f64(start)) / 1000.;
+        let variables = |trial: &Trial| -> String {
+            (0..self.variable_labels.len())
+                .map(|i| tsv_value(trial.variables.get(i).map_or("", |v| v.as_str())))
+                .map(|v| format!("\t{v}"))
+                .collect()
+        };
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        write!(writer, "onset\tduration\ttrial_type\ttrial_id\tmessage")?;
+        for label in &self.variable_labels {
+            write!(writer, "\t{}", tsv_value(label))?;
+        }
+        writeln!(writer)?;
+
+        for trial in &self.trials {
+            let duration = (decimal_to_f64(trial.time_record.end)
+                - decimal_to_f64(trial.time_record.start))
+                / 1000.;
+            writeln!(
+                writer,
+                "{:.6}\t{}\ttrial\t{}\tn/a{}",
+                onset(trial.time_record.start),
+                na((duration >= 0.).then(|| format!("{duration:.6}"))),
+                trial.id,
+                variables(trial),
+            )?;
+            for msg in &trial.messages {
+                writeln!(
+                    writer,
+                    "{:.6}\t0\tmessage\t{}\t{}{}",
+                    onset(msg.time),
+                    trial.id,
+                    tsv_value(&msg.text),
+                    variables(trial),
+                )?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn events_sidecar(&self) -> Value {
+        let mut sidecar = json!({
+            "onset": {
+                "Description": "Onset in seconds since the first eye-tracking sample",
+                "Units": "s",
+            },
+            "duration": {
+                "Description": "Duration of the trial, zero for messages",
+                "Units": "s",
+            },
+            "trial_type": {
+                "Description": "Kind of event",
+                "Levels": {
+                    "trial": "Trial, from TRIALID to TRIAL_RESULT",
+                    "message": "Message sent to the tracker during the trial",
+                },
+            },
+            "trial_id": {
+                "Description": "Trial the event belongs to",
+            },
+            "message": {
+                "Description": "Message text",
+            },
+        });
+        for label in &self.variable_labels {
+            sidecar[tsv_value(label)] = json!({ "Description": "Trial variable" });
+        }
+        sidecar
+    }
+
+    /// Writes the experiment into the BIDS dataset at `root`, creating the dataset description
+    /// if it does not exist. Returns the written files.
+    pub fn write_bids<P: AsRef<Path>>(
+        &self,
+        root: P,
+        options: &BidsOptions,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let root = root.as_ref();
+        // Required in the physio sidecar
+        let sampling_rate = self
+            .meta
+            .sampling_rate
+            .map(decimal_to_f64)
+            .or(options.sampling_rate)
+            .ok_or_else(|| {
+                anyhow!("Recording does not specify the sampling rate, set it in the options")
+            })?;
+        let start = self
+            .first_sample_time()
+            .ok_or_else(|| anyhow!("Experiment has no samples"))?;
+
+        let dir = root.join(options.directory());
+        std::fs::create_dir_all(&dir)?;
+        let prefix = options.prefix();
+        let mut written = Vec::new();
+
+        let description = root.join("dataset_description.json");
+        if !description.exists() {
+            write_json(
+                &description,
+                &json!({
+                    "Name": options.task,
+                    "BIDSVersion": "1.10.0",
+                    "DatasetType": "raw",
+                }),
+            )?;
+            written.push(description);
+        }
+
+        // One file per eye of the recording configuration (`RECCFG`), or per eye with samples
+        // in recordings without one
+        let eyes: Vec<Eye> = if self.meta.recorded_eyes.is_empty() {
+            [Eye::Left, Eye::Right]
+                .into_iter()
+                .filter(|&eye| {
+                    self.trials
+                        .iter()
+                        .flat_map(|t| &t.samples)
+                        .any(|s| s.eye(eye).is_some())
+                })
+                .collect()
+        } else {
+            self.meta.recorded_eyes.clone()
+        };
+
+        for (i, &eye) in eyes.iter().enumerate() {
+            let name = format!("{prefix}_recording-eye{}_physio", i + 1);
+            let physio = dir.join(format!("{name}.tsv.gz"));
+            self.write_physio(&physio, eye)?;
+            let sidecar = dir.join(format!("{name}.json"));
+            write_json(&sidecar, &self.physio_sidecar(eye, sampling_rate))?;
+            written.extend([physio, sidecar]);
+        }
+
+        let events = dir.join(format!("{prefix}_events.tsv"));
+        self.write_events(&events, start)?;
+        let sidecar = dir.join(format!("{prefix}_events.json"));
+        write_json(&sidecar, &self.events_sidecar())?;
+        written.extend([events, sidecar]);
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asc_to_generic;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    const ASC: &str = "\
+** DATE: Wed Jun 14 10:00:00 2023
+MSG\t1000 TRIALID 1
+1001\t100.0\t200.0\t1000.0\t.\t.\t0.0\t1.0\t2.0\t.\t.\t30.0\t31.0\t0.0\t.....
+MSG\t1001 stimulus on
+1002\t.\t.\t0.0\t.\t.\t0.0\t.\t.\t.\t.\t30.0\t31.0\t0.0\t.....
+MSG\t1030 TRIAL_RESULT 0
+MSG\t1040 between trials
+";
+
+    #[test]
+    fn test_write_bids() {
+        let exp = asc_to_generic(ASC).unwrap();
+        let root = std::env::temp_dir().join(format!("ascc-bids-{}", std::process::id()));
+        let mut options = BidsOptions::new("01", "reading");
+        assert!(exp.write_bids(&root, &options).is_err());
+
+        options.sampling_rate = Some(1000.);
+        let written = exp.write_bids(&root, &options).unwrap();
+        let dir = root.join("sub-01").join("beh");
+        let name = |file: &str| dir.join(format!("sub-01_task-reading_{file}"));
+        assert_eq!(
+            written,
+            [
+                root.join("dataset_description.json"),
+                name("recording-eye1_physio.tsv.gz"),
+                name("recording-eye1_physio.json"),
+                name("events.tsv"),
+                name("events.json"),
+            ]
+        );
+
+        let mut physio = String::new();
+        GzDecoder::new(File::open(&written[1]).unwrap())
+            .read_to_string(&mut physio)
+            .unwrap();
+        assert_eq!(physio, "1001\t100.0\t200.0\t1000.0\n1002\tn/a\tn/a\tn/a\n");
+
+        let sidecar: Value = serde_json::from_reader(File::open(&written[2]).unwrap()).unwrap();
+        assert_eq!(sidecar["SamplingFrequency"], 1000.);
+        assert_eq!(sidecar["RecordedEye"], "left");
+
+        // Onsets relative to the first sample, messages outside trials are not exported
+        let events = std::fs::read_to_string(&written[3]).unwrap();
+        assert_eq!(
+            events,
+            "onset\tduration\ttrial_type\ttrial_id\tmessage\n\
+             -0.001000\t0.030000\ttrial\t1\tn/a\n\
+             0.000000\t0\tmessage\t1\tstimulus on\n"
+        );
+
+        // The recording configuration takes precedence over the eyes with samples
+        let mut exp = exp;
+        exp.meta.recorded_eyes = vec![Eye::Right];
+        let written = exp.write_bids(&root, &options).unwrap();
+        let sidecar: Value = serde_json::from_reader(File::open(&written[1]).unwrap()).unwrap();
+        assert_eq!(sidecar["RecordedEye"], "right");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+//! Opt-in cache of parsed recordings.
+//!
+//! Parsing a large ASC file takes much longer than loading its archive, so [`ParseCache`] stores
+//! the parsed experiment as a container in a cache directory. Entries are keyed by the path,
+//! size and modification time of the source file and by the parser and schema versions, so
+//! entries of modified sources or of older versions of the library are never used.
+//!
+//! Each entry is a `<key>.dat` container with a `<key>.json` file describing the source.
+
+use crate::asc::PARSER_VERSION;
+use crate::container::{self, SCHEMA_VERSION};
+use crate::generic::Experiment;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheEntry {
+    /// Canonical path of the parsed file
+    pub source: PathBuf,
+    pub source_size: u64,
+    /// Modification time of the source, in nanoseconds since the Unix epoch
+    pub source_modified: u128,
+    pub parser_version: u32,
+    pub schema_version: u32,
+    pub tool_version: String,
+}
+
+impl CacheEntry {
+    fn for_source(path: &Path) -> anyhow::Result<Self> {
+        let source = path
+            .canonicalize()
+            .with_context(|| format!("Could not open {}", path.display()))?;
+        let metadata = source.metadata()?;
+        Ok(CacheEntry {
+            source,
+            source_size: metadata.len(),
+            source_modified: metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos(),
+            parser_version: PARSER_VERSION,
+            schema_version: SCHEMA_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        })
+    }
+
+    /// Name of the entry's files, the start of a SHA-256 digest of the entry so it is the same
+    /// for every build and platform.
+    fn key(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.source.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(self.source_size.to_le_bytes());
+        hasher.update(self.source_modified.to_le_bytes());
+        hasher.update(self.parser_version.to_le_bytes());
+        hasher.update(self.schema_version.to_le_bytes());
+        hasher.update(self.tool_version.as_bytes());
+        hasher.finalize()[..16]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    /// Whether the source still exists unchanged and the entry was written by this version.
+    pub fn is_valid(&self) -> bool {
+        CacheEntry::for_source(&self.source).is_ok_and(|current| &current == self)
+    }
+}
+
+pub struct ParseCache {
+    dir: PathBuf,
+}
+
+impl Default for ParseCache {
+    fn default() -> Self {
+        ParseCache::new(ParseCache::default_dir())
+    }
+}
+
+impl ParseCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        ParseCache { dir: dir.into() }
+    }
+
+    /// `$ASCC_CACHE_DIR`, or `ascc` in the user cache directory (`$XDG_CACHE_HOME`,
+    /// `~/.cache` or `%LOCALAPPDATA%`), or in the temporary directory.
+    pub fn default_dir() -> PathBuf {
+        if let Some(dir) = std::env::var_os("ASCC_CACHE_DIR") {
+            return dir.into();
+        }
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| Path::new(&h).join(".cache")))
+            .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+            .unwrap_or_else(std::env::temp_dir);
+        base.join("ascc")
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Loads `path` from the cache, parsing it with [`Experiment::load`] and storing the result
+    /// if there is no valid entry.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<Experiment> {
+        self.load_with(path, |source| Experiment::load(source))
+    }
+
+    /// Like [`ParseCache::load`], but parses the source with `parse` if there is no valid entry,
+    /// e.g. to report the progress of parsing. `parse` is not called on a cache hit.
+    pub fn load_with<P, F>(&self, path: P, parse: F) -> anyhow::Result<Experiment>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(&Path) -> anyhow::Result<Experiment>,
+    {
+        let entry = CacheEntry::for_source(path.as_ref())?;
+        let key = entry.key();
+        // An unreadable entry, or one describing another source, is treated like a missing one
+        // and overwritten
+        let stored = File::open(self.dir.join(format!("{key}.json")))
+            .ok()
+            .and_then(|file| serde_json::from_reader::<_, CacheEntry>(BufReader::new(file)).ok());
+        if stored.as_ref() == Some(&entry) {
+            if let Ok(exp) = container::load_experiment(self.dir.join(format!("{key}.dat"))) {
+                return Ok(exp);
+            }
+        }
+
+        let exp = parse(&entry.source)?;
+        self.store(&entry, &exp)
+            .with_context(|| format!("Could not write to cache {}", self.dir.display()))?;
+        Ok(exp)
+    }
+
+    fn store(&self, entry: &CacheEntry, exp: &Experiment) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let key = entry.key();
+        // Written under a temporary name and renamed, so concurrent loads never see a partial
+        // archive
+        let tmp = self.dir.join(format!("{key}.{}.tmp", std::process::id()));
+        container::save_experiment(&tmp, exp, false)?;
+        std::fs::rename(&tmp, self.dir.join(format!("{key}.dat")))?;
+        let writer = BufWriter::new(File::create(self.dir.join(format!("{key}.json")))?);
+        serde_json::to_writer_pretty(writer, entry)?;
+        Ok(())
+    }
+
+    /// Lists the cached entries with the size of their archives in bytes.
+    pub fn entries(&self) -> anyhow::Result<Vec<(CacheEntry, u64)>> {
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut entries = Vec::new();
+        for file in std::fs::read_dir(&self.dir)? {
+            let path = file?.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                let Ok(entry) =
+                    serde_json::from_reader::<_, CacheEntry>(BufReader::new(File::open(&path)?))
+                else {
+                    continue;
+                };
+                let size = path
+                    .with_extension("dat")
+                    .metadata()
+                    .map(|m| m.len())
+                    .unwrap_or_default();
+                entries.push((entry, size));
+            }
+        }
+        entries.sort_by(|a, b| a.0.source.cmp(&b.0.source));
+        Ok(entries)
+    }
+
+    /// Removes all entries, or only the invalid ones if `stale_only` is set. Returns the number
+    /// of removed entries.
+    pub fn clear(&self, stale_only: bool) -> anyhow::Result<usize> {
+        let mut removed = 0;
+        for (entry, _) in self.entries()? {
+            if stale_only && entry.is_valid() {
+                continue;
+            }
+            let key = entry.key();
+            for ext in ["dat", "json"] {
+                let path = self.dir.join(format!("{key}.{ext}"));
+                if path.exists() {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::Format;
+
+    #[test]
+    fn test_cache() {
+        let dir = std::env::temp_dir().join(format!("ascc-cache-{}", std::process::id()));
+        let source = dir.join("recording.json");
+        std::fs::create_dir_all(&dir).unwrap();
+        let exp = Experiment {
+            meta: Default::default(),
+            variable_labels: vec!["condition".to_string()],
+            trials: Vec::new(),
+        };
+        exp.save(&source, Format::Json).unwrap();
+
+        let cache = ParseCache::new(dir.join("cache"));
+        assert!(cache.entries().unwrap().is_empty());
+        let exp = cache.load(&source).unwrap();
+        assert_eq!(exp.variable_labels, ["condition"]);
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(entries[0].0.is_valid());
+        let hit = cache.load_with(&source, |_| panic!("parsed on a cache hit"));
+        assert_eq!(hit.unwrap().variable_labels, ["condition"]);
+
+        // An archive whose description does not match the source is parsed again
+        let mut other = entries[0].0.clone();
+        other.source = dir.join("other.json");
+        let key = entries[0].0.key();
+        let stored = cache.dir().join(format!("{key}.json"));
+        std::fs::write(&stored, serde_json::to_vec(&other).unwrap()).unwrap();
+        let renamed = Experiment {
+            variable_labels: vec!["block".to_string()],
+            ..exp
+        };
+        container::save_experiment(cache.dir().join(format!("{key}.dat")), &renamed, false)
+            .unwrap();
+        assert_eq!(cache.load(&source).unwrap().variable_labels, ["condition"]);
+        assert_eq!(cache.entries().unwrap()[0].0, entries[0].0);
+
+        assert_eq!(cache.clear(true).unwrap(), 0);
+        assert_eq!(cache.clear(false).unwrap(), 1);
+        assert!(cache.entries().unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_key_is_stable() {
+        let entry = CacheEntry {
+            source: PathBuf::from("/data/p01.asc"),
+            source_size: 1024,
+            source_modified: 1_686_736_800_000_000_000,
+            parser_version: 1,
+            schema_version: 1,
+            tool_version: "0.1.0".to_string(),
+        };
+        // Entries written by other builds of this version must still be found
+        assert_eq!(entry.key(), "3beae75b378b43934d0f8e5b083820ad");
+    }
+}
@@ -0,0 +1,367 @@
+//! Chunked containers with per-trial random access.
+//!
+//! A chunked container has the usual [container header](crate::container) with the chunked flag
+//! set, followed by an index and one chunk per trial:
+//!
+//! | bytes | content                                                    |
+//! |-------|------------------------------------------------------------|
+//! | 8     | length of the index, u64 little endian                     |
+//! | n     | rkyv archive of the index                                  |
+//! |       | zero padding to a multiple of 16 bytes                     |
+//! |       | trial chunks, rkyv archives of [`Trial`], each gzipped if the compressed flag is set |
+//!
+//! Each chunk starts at a multiple of 16 bytes, so the trials of uncompressed containers can be
+//! accessed in place, see [`crate::mapped`].
+//!
+//! The index holds the experiment metadata and, per trial, the id, time range, variables and
+//! position of the chunk, so trials can be listed without reading any of the chunks.
+
+use crate::container::{Header, MAGIC, SCHEMA_VERSION};
+use crate::generic::{Experiment, MetaData, TimeRecord, Trial};
+use anyhow::{anyhow, bail, Context};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rayon::prelude::*;
+use rkyv::AlignedVec;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const ALIGNMENT: usize = 16;
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Debug, Clone)]
+#[archive(check_bytes)]
+pub struct TrialEntry {
+    pub id: u32,
+    pub time_record: TimeRecord,
+    pub variables: Vec<String>,
+    pub sample_count: u64,
+    /// Offset of the chunk from the start of the chunk section
+    pub(crate) offset: u64,
+    /// Length of the chunk in bytes
+    pub(crate) length: u64,
+}
+
+#[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)]
+#[archive(check_bytes)]
+pub(crate) struct ChunkIndex {
+    pub(crate) meta: MetaData,
+    pub(crate) variable_labels: Vec<String>,
+    pub(crate) trials: Vec<TrialEntry>,
+}
+
+fn aligned(bytes: &[u8]) -> AlignedVec {
+    let mut archive = AlignedVec::with_capacity(bytes.len());
+    archive.extend_from_slice(bytes);
+    archive
+}
+
+/// Writes `experiment` as a chunked container, compressing each trial if `compressed` is set.
+pub fn write_chunked<W: Write>(
+    mut writer: W,
+    experiment: &Experiment,
+    compressed: bool,
+) -> anyhow::Result<()> {
+    let chunks = experiment
+        .trials
+        .par_iter()
+        .map(|trial| {
+            let bytes = rkyv::to_bytes::<_, 1024>(trial)?;
+            if compressed {
+                let mut e = GzEncoder::new(Vec::new(), Compression::fast());
+                e.write_all(&bytes)?;
+                Ok(e.finish()?)
+            } else {
+                Ok(bytes.to_vec())
+            }
+        })
+        .collect::<anyhow::Result<Vec<Vec<u8>>>>()?;
+
+    // Chunks start at aligned offsets, the gaps are zero padding
+    let mut end = 0u64;
+    let entries = experiment
+        .trials
+        .iter()
+        .zip(&chunks)
+        .map(|(trial, chunk)| {
+            let offset = end.next_multiple_of(ALIGNMENT as u64);
+            end = offset + chunk.len() as u64;
+            TrialEntry {
+                id: trial.id,
+                time_record: trial.time_record,
+                variables: trial.variables.clone(),
+                sample_count: trial.samples.len() as u64,
+                offset,
+                length: chunk.len() as u64,
+            }
+        })
+        .collect();
+    let index = ChunkIndex {
+        meta: experiment.meta.clone(),
+        variable_labels: experiment.variable_labels.clone(),
+        trials: entries,
+    };
+    let index_bytes = rkyv::to_bytes::<_, 256>(&index)?;
+
+    let header = Header {
+        chunked: true,
+        ..Header::current(compressed)
+    };
+    header.write(&mut writer)?;
+    writer.write_all(&(index_bytes.len() as u64).to_le_bytes())?;
+    writer.write_all(&index_bytes)?;
+    let padding = (8 + index_bytes.len()).next_multiple_of(ALIGNMENT) - (8 + index_bytes.len());
+    writer.write_all(&[0; ALIGNMENT][..padding])?;
+    let mut end = 0;
+    for (chunk, entry) in chunks.iter().zip(&index.trials) {
+        writer.write_all(&[0; ALIGNMENT][..(entry.offset - end) as usize])?;
+        writer.write_all(chunk)?;
+        end = entry.offset + entry.length;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn save_chunked<P: AsRef<Path>>(
+    path: P,
+    experiment: &Experiment,
+    compressed: bool,
+) -> anyhow::Result<()> {
+    write_chunked(BufWriter::new(File::create(path)?), experiment, compressed)
+}
+
+/// Reader of chunked containers that loads trials on demand.
+pub struct ChunkedReader<R> {
+    reader: R,
+    header: Header,
+    index: ChunkIndex,
+    /// Position of the first chunk in the file
+    data_offset: u64,
+}
+
+impl ChunkedReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+        Self::new(BufReader::new(file))
+            .with_context(|| format!("Could not load {}", path.display()))
+    }
+}
+
+impl<R: Read + Seek> ChunkedReader<R> {
+    /// Reads the header and index of the container at the start of `reader`.
+    ///
+    /// All lengths and chunk positions are checked against the size of the file, so a corrupt
+    /// or truncated file is an error here rather than when loading trials.
+    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+        let file_length = reader.seek(SeekFrom::End(0))?;
+        reader.rewind()?;
+        let fits = |start: usize, length: u64| {
+            (start as u64)
+                .checked_add(length)
+                .is_some_and(|end| end <= file_length)
+        };
+
+        let mut bytes = vec![0; 20];
+        reader.read_exact(&mut bytes)?;
+        if !bytes.starts_with(&MAGIC) {
+            bail!("Not an experiment container");
+        }
+        let length = u32::from_le_bytes(bytes[16..20].try_into()?) as usize;
+        if !fits(20, length as u64) {
+            bail!("Truncated container header");
+        }
+        bytes.resize((20 + length).next_multiple_of(ALIGNMENT), 0);
+        reader.read_exact(&mut bytes[20..])?;
+        let (header, header_length) = Header::parse(&bytes)?.context("Invalid container header")?;
+        if !header.chunked {
+            bail!("Container is not chunked");
+        }
+        if header.schema_version != SCHEMA_VERSION {
+            bail!(
+                "Chunked container has schema version {}, only version {SCHEMA_VERSION} is supported",
+                header.schema_version
+            );
+        }
+
+        let mut length = [0; 8];
+        reader.read_exact(&mut length)?;
+        let length = u64::from_le_bytes(length);
+        if !fits(header_length + 8, length) {
+            bail!("Chunk index of {length} bytes exceeds the file size");
+        }
+        let length = length as usize;
+        let mut index = vec![0; length];
+        reader.read_exact(&mut index)?;
+        let index = rkyv::from_bytes::<ChunkIndex>(&aligned(&index))
+            .map_err(|e| anyhow!("Invalid chunk index: {e}"))?;
+
+        let data_offset = header_length + (8 + length).next_multiple_of(ALIGNMENT);
+        for entry in &index.trials {
+            let in_file = entry
+                .offset
+                .checked_add(entry.length)
+                .is_some_and(|end| fits(data_offset, end));
+            if !in_file {
+                bail!("Chunk of trial {} exceeds the file size", entry.id);
+            }
+        }
+
+        Ok(ChunkedReader {
+            reader,
+            header,
+            index,
+            data_offset: data_offset as u64,
+        })
+    }
+
+    /// Splits the reader into the header, the index and the position of the first chunk.
+    pub(crate) fn into_parts(self) -> (Header, ChunkIndex, u64) {
+        (self.header, self.index, self.data_offset)
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn meta(&self) -> &MetaData {
+        &self.index.meta
+    }
+
+    pub fn variable_labels(&self) -> &[String] {
+        &self.index.variable_labels
+    }
+
+    pub fn entries(&self) -> &[TrialEntry] {
+        &self.index.trials
+    }
+
+    /// Position of the trial with the given id.
+    pub fn position(&self, id: u32) -> Option<usize> {
+        self.index.trials.iter().position(|t| t.id == id)
+    }
+
+    /// Reads and decompresses the trial at `index`.
+    pub fn load_trial(&mut self, index: usize) -> anyhow::Result<Trial> {
+        let Some(entry) = self.index.trials.get(index) else {
+            bail!(
+                "Trial index {index} out of range, the container has {} trials",
+                self.index.trials.len()
+            );
+        };
+        self.reader
+            .seek(SeekFrom::Start(self.data_offset + entry.offset))?;
+        let mut chunk = vec![0; entry.length as usize];
+        self.reader.read_exact(&mut chunk)?;
+        if self.header.compressed {
+            let mut data = Vec::new();
+            GzDecoder::new(chunk.as_slice())
+                .read_to_end(&mut data)
+                .with_context(|| format!("Could not decompress trial {}", entry.id))?;
+            chunk = data;
+        }
+        rkyv::from_bytes::<Trial>(&aligned(&chunk))
+            .map_err(|e| anyhow!("Invalid archive of trial {}: {e}", entry.id))
+    }
+
+    pub fn load_trials<I: IntoIterator<Item = usize>>(
+        &mut self,
+        indices: I,
+    ) -> anyhow::Result<Vec<Trial>> {
+        indices.into_iter().map(|i| self.load_trial(i)).collect()
+    }
+
+    /// Loads all trials.
+    pub fn to_experiment(&mut self) -> anyhow::Result<Experiment> {
+        Ok(Experiment {
+            meta: self.index.meta.clone(),
+            variable_labels: self.index.variable_labels.clone(),
+            trials: self.load_trials(0..self.index.trials.len())?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::from_bytes;
+    use crate::generic::Sample;
+    use crate::Decimal;
+    use std::io::Cursor;
+    use std::str::FromStr;
+
+    fn experiment() -> Experiment {
+        let time = |t: u32| Decimal::from_str(&t.to_string()).unwrap();
+        let trials = (1..=3)
+            .map(|id| Trial {
+                samples: (0..id * 5)
+                    .map(|t| Sample {
+                        time: time(id * 100 + t),
+                        left: None,
+                        right: None,
+                        resolution: None,
+                        interpolated: false,
+                    })
+                    .collect(),
+                variables: vec![format!("cond{id}")],
+                ..Trial::empty(
+                    id,
+                    TimeRecord {
+                        start: time(id * 100),
+                        end: time(id * 100 + 50),
+                    },
+                )
+            })
+            .collect();
+        Experiment {
+            meta: MetaData::default(),
+            variable_labels: vec!["condition".to_string()],
+            trials,
+        }
+    }
+
+    #[test]
+    fn test_chunked_roundtrip() {
+        let exp = experiment();
+        for compressed in [false, true] {
+            let mut bytes = Vec::new();
+            write_chunked(&mut bytes, &exp, compressed).unwrap();
+
+            let mut reader = ChunkedReader::new(Cursor::new(&bytes)).unwrap();
+            assert_eq!(reader.variable_labels(), ["condition"]);
+            let ids: Vec<u32> = reader.entries().iter().map(|e| e.id).collect();
+            assert_eq!(ids, [1, 2, 3]);
+            assert_eq!(reader.entries()[1].sample_count, 10);
+
+            let trial = reader.load_trial(reader.position(3).unwrap()).unwrap();
+            assert_eq!(trial.samples.len(), 15);
+            assert_eq!(trial.variables, ["cond3"]);
+            assert!(reader.load_trial(3).is_err());
+
+            let all = from_bytes(&bytes).unwrap();
+            assert_eq!(all.trials.len(), 3);
+            assert_eq!(all.trials[0].samples.len(), 5);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_lengths() {
+        let mut bytes = Vec::new();
+        write_chunked(&mut bytes, &experiment(), false).unwrap();
+        let (_, offset) = Header::parse(&bytes).unwrap().unwrap();
+
+        // The last chunk is cut short
+        let truncated = &bytes[..bytes.len() - 1];
+        assert!(ChunkedReader::new(Cursor::new(truncated)).is_err());
+
+        let mut corrupt = bytes.clone();
+        corrupt[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(ChunkedReader::new(Cursor::new(&corrupt)).is_err());
+
+        let mut corrupt = bytes;
+        corrupt[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(ChunkedReader::new(Cursor::new(&corrupt)).is_err());
+    }
+}
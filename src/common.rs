@@ -8,7 +8,19 @@ use pyo3::pyclass;
 use rkyv::Archive;
 
 #[derive(
-    rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Serialize, Deserialize, Debug, Clone, Copy,
+    rkyv::Archive,
+    rkyv::Serialize,
+    rkyv::Deserialize,
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
 )]
 #[archive(check_bytes)]
 #[cfg_attr(feature = "py-ext", pyclass)]
@@ -28,3 +40,12 @@ impl FromStr for Eye {
         }
     }
 }
+
+impl Eye {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Eye::Left => "left",
+            Eye::Right => "right",
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! Versioned on-disk container for experiments.
+//!
+//! A container starts with a header followed by the rkyv archive of an [`Experiment`],
+//! optionally gzip compressed:
+//!
+//! | bytes | content                                        |
+//! |-------|------------------------------------------------|
+//! | 8     | magic, `ASCTOOLS`                              |
+//! | 4     | schema version, u32 little endian              |
+//! | 4     | flags, u32 little endian, see below            |
+//! | 4     | length of the tool version string              |
+//! | n     | tool version string, utf-8                     |
+//! |       | zero padding to a multiple of 16 bytes         |
+//!
+//! Flag bit 0 is set if the payload is gzipped, bit 1 if the payload is split into per-trial
+//! chunks, see [`crate::chunked`].
+//!
+//! Files without the magic are read as headerless archives of schema version 1, which is what
+//! older versions of the converter wrote to `.dat` and (gzipped) `.dat.archive` files.
+//!
+//! The padding keeps the payload aligned for rkyv. The trials of uncompressed chunked containers
+//! can be accessed in place, see [`crate::mapped`].
+//!
+//! When changing the layout of any archived type, bump [`SCHEMA_VERSION`], freeze the previous
+//! layout in a `v<N>` module below and add the migration step to [`migrate`].
+
+use crate::chunked::ChunkedReader;
+use crate::generic::Experiment;
+use anyhow::{anyhow, bail, Context};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rkyv::AlignedVec;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor, Read, Write};
+use std::path::Path;
+
+pub const MAGIC: [u8; 8] = *b"ASCTOOLS";
+/// Schema version written by this version of the library
+pub const SCHEMA_VERSION: u32 = 2;
+
+const FLAG_COMPRESSED: u32 = 1;
+const FLAG_CHUNKED: u32 = 2;
+/// Alignment of the payload relative to the start of the file
+const PAYLOAD_ALIGNMENT: usize = 16;
+pub(crate) const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub schema_version: u32,
+    pub compressed: bool,
+    /// Trials are stored as separate chunks behind an index
+    pub chunked: bool,
+    /// Name and version of the tool that wrote the file
+    pub tool_version: String,
+}
+
+impl Header {
+    pub fn current(compressed: bool) -> Self {
+        Header {
+            schema_version: SCHEMA_VERSION,
+            compressed,
+            chunked: false,
+            tool_version: format!("asc-tools {}", env!("CARGO_PKG_VERSION")),
+        }
+    }
+
+    pub(crate) fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(self.schema_version.to_le_bytes());
+        let mut flags = 0;
+        if self.compressed {
+            flags |= FLAG_COMPRESSED;
+        }
+        if self.chunked {
+            flags |= FLAG_CHUNKED;
+        }
+        bytes.extend(flags.to_le_bytes());
+        bytes.extend((self.tool_version.len() as u32).to_le_bytes());
+        bytes.extend(self.tool_version.as_bytes());
+        bytes.resize(bytes.len().next_multiple_of(PAYLOAD_ALIGNMENT), 0);
+        writer.write_all(&bytes)
+    }
+
+    /// Parses the header at the start of `bytes`, returning it with the offset of the payload.
+    /// Returns `None` for headerless files.
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Option<(Self, usize)>> {
+        if !bytes.starts_with(&MAGIC) {
+            return Ok(None);
+        }
+        let u32_at = |offset: usize| -> anyhow::Result<u32> {
+            let b = bytes
+                .get(offset..offset + 4)
+                .ok_or_else(|| anyhow!("Truncated container header"))?;
+            Ok(u32::from_le_bytes(b.try_into()?))
+        };
+        let schema_version = u32_at(8)?;
+        let flags = u32_at(12)?;
+        let length = u32_at(16)? as usize;
+        let tool_version = bytes
+            .get(20..20 + length)
+            .ok_or_else(|| anyhow!("Truncated container header"))?;
+        let header = Header {
+            schema_version,
+            compressed: flags & FLAG_COMPRESSED != 0,
+            chunked: flags & FLAG_CHUNKED != 0,
+            tool_version: String::from_utf8(tool_version.to_vec())?,
+        };
+        Ok(Some((
+            header,
+            (20 + length).next_multiple_of(PAYLOAD_ALIGNMENT),
+        )))
+    }
+}
+
+/// Writes `experiment` with a header of the current schema version.
+pub fn write_experiment<W: Write>(
+    mut writer: W,
+    experiment: &Experiment,
+    compressed: bool,
+) -> anyhow::Result<()> {
+    let bytes = rkyv::to_bytes::<_, 256>(experiment)?;
+    Header::current(compressed).write(&mut writer)?;
+    if compressed {
+        let mut e = GzEncoder::new(writer, Compression::fast());
+        e.write_all(&bytes)?;
+        e.finish()?.flush()?;
+    } else {
+        writer.write_all(&bytes)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Reads a container or a headerless archive, migrating older schema versions.
+pub fn read_experiment<R: Read>(mut reader: R) -> anyhow::Result<Experiment> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    from_bytes(&bytes)
+}
+
+pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Experiment> {
+    let (version, compressed, payload) = match Header::parse(bytes)? {
+        Some((header, _)) if header.chunked => {
+            return ChunkedReader::new(Cursor::new(bytes))?.to_experiment();
+        }
+        Some((header, offset)) => {
+            let payload = bytes
+                .get(offset..)
+                .ok_or_else(|| anyhow!("Truncated container, the payload is missing"))?;
+            (header.schema_version, header.compressed, payload)
+        }
+        None => (1, bytes.starts_with(&GZIP_MAGIC), bytes),
+    };
+    if version > SCHEMA_VERSION {
+        bail!(
+            "File has schema version {version}, newer than the supported version {SCHEMA_VERSION}"
+        );
+    }
+
+    // rkyv requires the archive to be aligned
+    let mut archive = AlignedVec::new();
+    if compressed {
+        let mut data = Vec::new();
+        GzDecoder::new(payload)
+            .read_to_end(&mut data)
+            .context("Could not decompress archive")?;
+        archive.extend_from_slice(&data);
+    } else {
+        archive.extend_from_slice(payload);
+    }
+    migrate(version, &archive)
+}
+
+/// Deserializes an archive of schema `version` and migrates it to the current schema.
+fn migrate(version: u32, archive: &[u8]) -> anyhow::Result<Experiment> {
+    let invalid = |e| anyhow!("Invalid archive of schema version {version}: {e}");
+    match version {
+        1 => {
+            let exp: v1::Experiment =
+                rkyv::from_bytes(archive).map_err(|e| invalid(e.to_string()))?;
+            Ok(exp.into())
+        }
+        SCHEMA_VERSION => rkyv::from_bytes(archive).map_err(|e| invalid(e.to_string())),
+        _ => bail!("Unknown schema version {version}"),
+    }
+}
+
+pub fn save_experiment<P: AsRef<Path>>(
+    path: P,
+    experiment: &Experiment,
+    compressed: bool,
+) -> anyhow::Result<()> {
+    write_experiment(BufWriter::new(File::create(path)?), experiment, compressed)
+}
+
+pub fn load_experiment<P: AsRef<Path>>(path: P) -> anyhow::Result<Experiment> {
+    let path = path.as_ref();
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    read_experiment(BufReader::new(file))
+        .with_context(|| format!("Could not load {}", path.display()))
+}
+
+/// Layout of schema version 1. Types that have not changed since are reused from
+/// [`crate::generic`].
+///
+/// Postcard files written at the time have the same layout, see [`crate::io`].
+pub(crate) mod v1 {
+    use crate::generic::{self, CameraFrame, EventRecord, EyeSampleData, RawSample, TargetInfo};
+    use crate::generic::{TimeRecord, Vector};
+    use crate::{Decimal, NaiveDateTime};
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Serialize, Deserialize)]
+    #[archive(check_bytes)]
+    pub struct Experiment {
+        pub meta: MetaData,
+        pub variable_labels: Vec<String>,
+        pub trials: Vec<Trial>,
+    }
+
+    #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Serialize, Deserialize)]
+    #[archive(check_bytes)]
+    pub struct MetaData {
+        pub recording_datetime: NaiveDateTime,
+        pub preamble_lines: Vec<String>,
+    }
+
+    #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Serialize, Deserialize)]
+    #[archive(check_bytes)]
+    pub struct Trial {
+        pub id: u32,
+        pub time_record: TimeRecord,
+        pub samples: Vec<Sample>,
+        pub raw_samples: Vec<RawSample>,
+        pub events: Vec<EventRecord>,
+        pub camera_frames: Vec<CameraFrame>,
+        pub variables: Vec<String>,
+        pub targets: HashMap<String, Vec<TargetInfo>>,
+    }
+
+    #[derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Serialize, Deserialize)]
+    #[archive(check_bytes)]
+    pub struct Sample {
+        pub time: Decimal,
+        pub left: Option<EyeSampleData>,
+        pub right: Option<EyeSampleData>,
+        pub resolution: Option<Vector>,
+    }
+
+    impl From<Experiment> for generic::Experiment {
+        fn from(value: Experiment) -> Self {
+            generic::Experiment {
+                meta: generic::MetaData {
+                    recording_datetime: value.meta.recording_datetime,
+                    preamble_lines: value.meta.preamble_lines,
+                    ..Default::default()
+                },
+                variable_labels: value.variable_labels,
+                trials: value.trials.into_iter().map(|t| t.into()).collect(),
+            }
+        }
+    }
+
+    impl From<Trial> for generic::Trial {
+        fn from(value: Trial) -> Self {
+            generic::Trial {
+                id: value.id,
+                time_record: value.time_record,
+                samples: value
+                    .samples
+                    .into_iter()
+                    .map(|s| generic::Sample {
+                        time: s.time,
+                        left: s.left,
+                        right: s.right,
+                        resolution: s.resolution,
+                        interpolated: false,
+                    })
+                    .collect(),
+                raw_samples: value.raw_samples,
+                events: value.events,
+                camera_frames: value.camera_frames,
+                variables: value.variables,
+                targets: value.targets,
+                messages: Vec::new(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::TimeRecord;
+    use crate::Decimal;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = Header::current(true);
+        let mut bytes = Vec::new();
+        header.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len() % PAYLOAD_ALIGNMENT, 0);
+        let (parsed, offset) = Header::parse(&bytes).unwrap().unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(offset, bytes.len());
+        assert!(Header::parse(b"not a container").unwrap().is_none());
+    }
+
+    fn v1_experiment() -> v1::Experiment {
+        let time = Decimal::from_str("1000").unwrap();
+        v1::Experiment {
+            meta: v1::MetaData {
+                recording_datetime: Default::default(),
+                preamble_lines: vec!["** DATE: Wed Jun 14 10:00:00 2023".to_string()],
+            },
+            variable_labels: vec!["condition".to_string()],
+            trials: vec![v1::Trial {
+                id: 1,
+                time_record: TimeRecord {
+                    start: time,
+                    end: time,
+                },
+                samples: vec![v1::Sample {
+                    time,
+                    left: None,
+                    right: None,
+                    resolution: None,
+                }],
+                raw_samples: Vec::new(),
+                events: Vec::new(),
+                camera_frames: Vec::new(),
+                variables: vec!["a".to_string()],
+                targets: HashMap::new(),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        let bytes = rkyv::to_bytes::<_, 256>(&v1_experiment()).unwrap();
+        let mut compressed = GzEncoder::new(Vec::new(), Compression::fast());
+        compressed.write_all(&bytes).unwrap();
+        let compressed = compressed.finish().unwrap();
+
+        for data in [bytes.to_vec(), compressed] {
+            let exp = from_bytes(&data).unwrap();
+            assert_eq!(exp.variable_labels, ["condition"]);
+            assert_eq!(exp.meta.preamble_lines.len(), 1);
+            assert!(exp.meta.sampling_rate.is_none());
+            let trial = &exp.trials[0];
+            assert_eq!(trial.variables, ["a"]);
+            assert!(trial.messages.is_empty());
+            assert!(!trial.samples[0].interpolated);
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let exp: Experiment = v1_experiment().into();
+        for compressed in [false, true] {
+            let mut bytes = Vec::new();
+            write_experiment(&mut bytes, &exp, compressed).unwrap();
+            let (header, _) = Header::parse(&bytes).unwrap().unwrap();
+            assert_eq!(header, Header::current(compressed));
+            let read = from_bytes(&bytes).unwrap();
+            assert_eq!(read.trials[0].samples.len(), 1);
+            assert_eq!(read.trials[0].variables, ["a"]);
+        }
+
+        let mut bytes = Vec::new();
+        write_experiment(&mut bytes, &exp, false).unwrap();
+        bytes[8..12].copy_from_slice(&(SCHEMA_VERSION + 1).to_le_bytes());
+        assert!(from_bytes(&bytes).is_err());
+        assert!(from_bytes(b"garbage").is_err());
+        // Cut within the padding after the header
+        assert!(from_bytes(&bytes[..40]).is_err());
+    }
+}
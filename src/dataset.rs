@@ -0,0 +1,497 @@
+//! Datasets of many recordings, e.g. several sessions of each participant of a study.
+//!
+//! Files are discovered with a glob or from a directory, participant and session ids are taken
+//! from the file names with a [`FilenamePattern`], and participant metadata can be attached from
+//! a CSV, TSV (like a BIDS `participants.tsv`) or TOML file.
+
+use crate::cache::ParseCache;
+use crate::generic::{Experiment, Trial};
+use crate::io::Format;
+use anyhow::{anyhow, bail, Context};
+use rayon::prelude::*;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "dataframes")]
+use crate::export::concat_aligned;
+#[cfg(feature = "dataframes")]
+use polars::prelude::*;
+
+/// Extracts participant and session ids from file names.
+#[derive(Debug, Clone)]
+pub struct FilenamePattern {
+    regex: Regex,
+}
+
+impl FilenamePattern {
+    /// Pattern with `{participant}` and `{session}` placeholders and `*` wildcards, matched
+    /// against the start of the file name, e.g. `sub-{participant}_ses-{session}`. Ids consist
+    /// of letters and digits.
+    pub fn new(template: &str) -> anyhow::Result<Self> {
+        let mut re = String::from("^");
+        let mut rest = template;
+        while let Some(i) = rest.find(['{', '*']) {
+            re.push_str(&regex::escape(&rest[..i]));
+            if rest[i..].starts_with('*') {
+                re.push_str(".*?");
+                rest = &rest[i + 1..];
+                continue;
+            }
+            let end = rest[i..]
+                .find('}')
+                .ok_or_else(|| anyhow!("Unclosed placeholder in {template}"))?;
+            match &rest[i + 1..i + end] {
+                name @ ("participant" | "session") => {
+                    re.push_str(&format!("(?P<{name}>[[:alnum:]]+)"))
+                }
+                name => bail!("Unknown placeholder {{{name}}}, expected participant or session"),
+            }
+            rest = &rest[i + end + 1..];
+        }
+        re.push_str(&regex::escape(rest));
+        Self::regex(&re)
+    }
+
+    /// Regular expression with `participant` and/or `session` named groups, matched against the
+    /// file name.
+    pub fn regex(re: &str) -> anyhow::Result<Self> {
+        Ok(FilenamePattern {
+            regex: Regex::new(re)?,
+        })
+    }
+
+    /// Participant and session id of `path`.
+    pub fn ids(&self, path: &Path) -> (Option<String>, Option<String>) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let Some(captures) = self.regex.captures(&name) else {
+            return (None, None);
+        };
+        let id = |group| captures.name(group).map(|m| m.as_str().to_string());
+        (id("participant"), id("session"))
+    }
+}
+
+/// Participant ids with and without the BIDS `sub-` prefix refer to the same participant.
+fn normalize_id(id: &str) -> &str {
+    id.strip_prefix("sub-").unwrap_or(id)
+}
+
+/// Recording formats picked up when discovering files in a directory. JSON is left out as
+/// directories often contain JSON sidecars.
+fn is_recording(path: &Path) -> bool {
+    matches!(
+        Format::from_path(path),
+        Some(
+            Format::Asc
+                | Format::Edf
+                | Format::Cbor
+                | Format::Postcard
+                | Format::Rkyv
+                | Format::Chunked
+        )
+    )
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else if is_recording(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Finds the files matching the glob `spec`, or all recordings below `spec` if it is a
+/// directory. The files are sorted by path.
+pub fn discover(spec: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if Path::new(spec).is_dir() {
+        walk(Path::new(spec), &mut files)?;
+    } else {
+        for path in glob::glob(spec)? {
+            let path = path?;
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Splits a line of a CSV or TSV file, handling double-quoted fields.
+fn split_delimited(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields.into_iter().map(|f| f.trim().to_string()).collect()
+}
+
+#[derive(Default)]
+pub struct DatasetOptions {
+    pub pattern: Option<FilenamePattern>,
+    /// Parse cache to load the recordings through
+    pub cache: Option<ParseCache>,
+}
+
+pub struct Session {
+    pub path: PathBuf,
+    pub participant: Option<String>,
+    pub session: Option<String>,
+    pub experiment: Experiment,
+}
+
+#[derive(Default)]
+pub struct Dataset {
+    pub sessions: Vec<Session>,
+    /// Files that could not be loaded
+    pub failures: Vec<(PathBuf, anyhow::Error)>,
+    /// Names of the participant metadata columns
+    pub participant_columns: Vec<String>,
+    /// Participant metadata by participant id, in the order of `participant_columns`
+    pub participants: HashMap<String, Vec<String>>,
+}
+
+impl Dataset {
+    /// Discovers the files of `spec` with [`discover`] and loads them.
+    pub fn open(spec: &str, options: &DatasetOptions) -> anyhow::Result<Dataset> {
+        let files = discover(spec)?;
+        if files.is_empty() {
+            bail!("No recordings found for {spec}");
+        }
+        Ok(Dataset::load(&files, options))
+    }
+
+    /// Loads `paths` in parallel. Files that fail to load are collected in
+    /// [`Dataset::failures`] instead of failing the whole dataset.
+    pub fn load(paths: &[PathBuf], options: &DatasetOptions) -> Dataset {
+        let results: Vec<(PathBuf, anyhow::Result<Experiment>)> = paths
+            .par_iter()
+            .map(|path| {
+                let exp = match &options.cache {
+                    Some(cache) => cache.load(path),
+                    None => Experiment::load(path),
+                };
+                (path.clone(), exp)
+            })
+            .collect();
+
+        let mut dataset = Dataset::default();
+        for (path, result) in results {
+            match result {
+                Ok(experiment) => {
+                    let (participant, session) = options
+                        .pattern
+                        .as_ref()
+                        .map_or((None, None), |p| p.ids(&path));
+                    dataset.sessions.push(Session {
+                        path,
+                        participant,
+                        session,
+                        experiment,
+                    });
+                }
+                Err(e) => dataset.failures.push((path, e)),
+            }
+        }
+        dataset
+    }
+
+    /// Attaches participant metadata from a CSV or TSV file with a header row, or from a TOML
+    /// file with one table per participant.
+    ///
+    /// In delimited files the participant id is taken from the `participant_id`, `participant`
+    /// or `subject` column, or else from the first column.
+    pub fn attach_participants<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not open {}", path.display()))?;
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match ext.as_str() {
+            "toml" => self.attach_toml(&text),
+            "tsv" => self.attach_delimited(&text, '\t'),
+            _ => self.attach_delimited(&text, ','),
+        }
+        .with_context(|| format!("Could not read participants from {}", path.display()))
+    }
+
+    fn attach_delimited(&mut self, text: &str, delimiter: char) -> anyhow::Result<()> {
+        let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+        let header = split_delimited(lines.next().unwrap_or_default(), delimiter);
+        let id_column = header
+            .iter()
+            .position(|h| matches!(h.as_str(), "participant_id" | "participant" | "subject"))
+            .unwrap_or(0);
+        self.participant_columns = header
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != id_column)
+            .map(|(_, h)| h.clone())
+            .collect();
+        self.participants.clear();
+        for line in lines {
+            let mut fields = split_delimited(line, delimiter);
+            fields.resize(header.len(), String::new());
+            let id = fields.remove(id_column);
+            self.participants
+                .insert(normalize_id(&id).to_string(), fields);
+        }
+        Ok(())
+    }
+
+    fn attach_toml(&mut self, text: &str) -> anyhow::Result<()> {
+        let value: toml::Value = toml::from_str(text)?;
+        let table = value
+            .as_table()
+            .ok_or_else(|| anyhow!("Expected a table per participant"))?;
+        let mut columns: Vec<String> = Vec::new();
+        for fields in table.values().filter_map(|v| v.as_table()) {
+            for key in fields.keys() {
+                if !columns.contains(key) {
+                    columns.push(key.clone());
+                }
+            }
+        }
+        self.participants = table
+            .iter()
+            .filter_map(|(id, v)| Some((id, v.as_table()?)))
+            .map(|(id, fields)| {
+                let values = columns
+                    .iter()
+                    .map(|c| match fields.get(c) {
+                        Some(toml::Value::String(s)) => s.clone(),
+                        Some(v) => v.to_string(),
+                        None => String::new(),
+                    })
+                    .collect();
+                (normalize_id(id).to_string(), values)
+            })
+            .collect();
+        self.participant_columns = columns;
+        Ok(())
+    }
+
+    /// Metadata `column` of `participant`.
+    pub fn participant_value(&self, participant: &str, column: &str) -> Option<&str> {
+        let i = self.participant_columns.iter().position(|c| c == column)?;
+        let row = self.participants.get(normalize_id(participant))?;
+        row.get(i).map(|v| v.as_str())
+    }
+
+    /// Ids of the participants with at least one loaded session, sorted.
+    pub fn participant_ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = self
+            .sessions
+            .iter()
+            .filter_map(|s| s.participant.as_deref())
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+
+    pub fn trials(&self) -> impl Iterator<Item = (&Session, &Trial)> {
+        self.sessions
+            .iter()
+            .flat_map(|s| s.experiment.trials.iter().map(move |t| (s, t)))
+    }
+
+    /// Trials of all sessions for which trial variable `variable` equals `value`.
+    pub fn trials_where(&self, variable: &str, value: &str) -> Vec<(&Session, &Trial)> {
+        self.sessions
+            .iter()
+            .filter_map(|s| Some((s, s.experiment.variable_index(variable)?)))
+            .flat_map(|(s, i)| {
+                s.experiment
+                    .trials
+                    .iter()
+                    .filter(move |t| t.variables.get(i).is_some_and(|v| v == value))
+                    .map(move |t| (s, t))
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "dataframes")]
+impl Dataset {
+    /// Stacks `table` of all sessions, prepending `participant`, `session` and `file` columns and
+    /// the participant metadata. Metadata columns are prefixed with `participant_` if their name
+    /// is taken.
+    fn long_format<F>(&self, table: F) -> PolarsResult<DataFrame>
+    where
+        F: Fn(&Experiment) -> PolarsResult<DataFrame> + Sync,
+    {
+        let frames = self
+            .sessions
+            .par_iter()
+            .map(|s| {
+                let df = table(&s.experiment)?;
+                let n = df.height();
+                let mut columns = vec![
+                    Series::new("participant", vec![s.participant.as_deref(); n]),
+                    Series::new("session", vec![s.session.as_deref(); n]),
+                    Series::new("file", vec![s.path.display().to_string(); n]),
+                ];
+                let row = s
+                    .participant
+                    .as_deref()
+                    .and_then(|p| self.participants.get(normalize_id(p)));
+                for (i, column) in self.participant_columns.iter().enumerate() {
+                    let taken = df.column(column).is_ok()
+                        || columns.iter().any(|s| s.name() == column.as_str());
+                    let name = if taken {
+                        format!("participant_{column}")
+                    } else {
+                        column.clone()
+                    };
+                    let value = row.and_then(|r| r.get(i)).map(|v| v.as_str());
+                    columns.push(Series::new(&name, vec![value; n]));
+                }
+                DataFrame::new(columns)?.hstack(df.get_columns())
+            })
+            .collect::<PolarsResult<Vec<_>>>()?;
+        concat_aligned(frames)
+    }
+
+    pub fn trial_variables(&self) -> PolarsResult<DataFrame> {
+        self.long_format(Experiment::trial_variables)
+    }
+
+    pub fn samples(&self) -> PolarsResult<DataFrame> {
+        self.long_format(Experiment::samples)
+    }
+
+    pub fn events(&self) -> PolarsResult<DataFrame> {
+        self.long_format(Experiment::events)
+    }
+
+    pub fn raw_samples(&self) -> PolarsResult<DataFrame> {
+        self.long_format(Experiment::raw_samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filename_pattern() {
+        let pattern = FilenamePattern::new("sub-{participant}_ses-{session}*").unwrap();
+        let ids = pattern.ids(Path::new("data/sub-01_ses-2_task-read.asc"));
+        assert_eq!(ids, (Some("01".to_string()), Some("2".to_string())));
+        assert_eq!(pattern.ids(Path::new("other.asc")), (None, None));
+        assert!(FilenamePattern::new("{subject}").is_err());
+
+        let pattern = FilenamePattern::new("P{participant}.asc").unwrap();
+        assert_eq!(pattern.ids(Path::new("P07.asc")).0.as_deref(), Some("07"));
+    }
+
+    #[test]
+    fn test_participants() {
+        let mut dataset = Dataset::default();
+        dataset
+            .attach_delimited(
+                "participant_id\tage\tgroup\nsub-01\t23\tcontrol\n02\t31\t\n",
+                '\t',
+            )
+            .unwrap();
+        assert_eq!(dataset.participant_value("01", "group"), Some("control"));
+        assert_eq!(dataset.participant_value("sub-02", "age"), Some("31"));
+
+        dataset
+            .attach_toml("[01]\nage = 23\ngroup = \"control\"\n[02]\nage = 31\n")
+            .unwrap();
+        assert_eq!(dataset.participant_value("01", "age"), Some("23"));
+        assert_eq!(dataset.participant_value("02", "group"), Some(""));
+
+        assert_eq!(
+            split_delimited(r#"a,"b, ""c""",d"#, ','),
+            ["a", r#"b, "c""#, "d"]
+        );
+    }
+
+    #[cfg(feature = "dataframes")]
+    #[test]
+    fn test_long_format_differing_recordings() {
+        use crate::common::Eye;
+        use crate::generic::{EventInfo, EventRecord, TimeRecord};
+        use crate::Decimal;
+        use std::str::FromStr;
+
+        let time_record = TimeRecord {
+            start: Decimal::from(1000),
+            end: Decimal::from(2000),
+        };
+        let blink = EventRecord {
+            time_record,
+            eye: Eye::Left,
+            resolution: None,
+            info: EventInfo::Blink,
+        };
+        let fixation = EventRecord {
+            resolution: Some([Decimal::from(30), Decimal::from(30)]),
+            info: EventInfo::Fixation {
+                average_position: [Decimal::from_str("512.5").unwrap(), Decimal::from(384)],
+                average_pupil_area: Decimal::from(900),
+            },
+            ..blink
+        };
+        let session = |name: &str, labels: &[&str], events: Vec<EventRecord>| Session {
+            path: PathBuf::from(name),
+            participant: Some(name.to_string()),
+            session: None,
+            experiment: Experiment {
+                meta: Default::default(),
+                variable_labels: labels.iter().map(|l| l.to_string()).collect(),
+                trials: vec![Trial {
+                    events,
+                    variables: labels.iter().map(|l| format!("{l}-1")).collect(),
+                    ..Trial::empty(1, time_record)
+                }],
+            },
+        };
+        // The first recording only has blinks, so its fixation and resolution columns are null
+        let dataset = Dataset {
+            sessions: vec![
+                session("01", &["condition"], vec![blink]),
+                session("02", &["condition", "block"], vec![fixation, blink]),
+            ],
+            ..Default::default()
+        };
+
+        let events = dataset.events().unwrap();
+        assert_eq!(events.height(), 3);
+        assert!(matches!(
+            events.column("average_pos_x").unwrap().dtype(),
+            DataType::Decimal(_, Some(1))
+        ));
+        assert_eq!(events.column("res_x").unwrap().null_count(), 2);
+        assert_eq!(events.column("block").unwrap().null_count(), 1);
+
+        let trials = dataset.trial_variables().unwrap();
+        assert_eq!(trials.height(), 2);
+        let block = trials.column("block").unwrap();
+        assert_eq!(block.get(1).unwrap(), AnyValue::Utf8("block-1"));
+    }
+}
@@ -38,10 +38,16 @@
 //     pub fn
 // }
 
-use crate::generic::{Experiment, Trial};
+use crate::analysis::quality::TrialQuality;
+use crate::generic::{EventInfo, EventRecord, Experiment, Trial};
+use crate::io::OutputFormat;
 use crate::Decimal;
+use anyhow::{anyhow, bail};
 use polars::prelude::AnyValue;
 use polars::prelude::*;
+use rayon::prelude::*;
+use std::fs::File;
+use std::path::{Path, PathBuf};
 
 impl Experiment {
     pub fn trial_variables(&self) -> PolarsResult<DataFrame> {
@@ -71,6 +77,233 @@ impl Experiment {
 
         df
     }
+
+    /// Builds one long-format table from the per-trial tables produced by `frame`, in parallel
+    /// over trials. Every row gets the trial id, the time relative to the trial start (from
+    /// `times`) and the values of the trial variables, null where a trial has fewer variables.
+    fn long_format<F, G>(&self, frame: F, times: G) -> PolarsResult<DataFrame>
+    where
+        F: Fn(&Trial) -> PolarsResult<DataFrame> + Sync,
+        G: Fn(&Trial) -> Vec<Option<Decimal>> + Sync,
+    {
+        let frames: Vec<DataFrame> = self
+            .trials
+            .par_iter()
+            .map(|t| {
+                let mut df = frame(t)?;
+                let n = df.height();
+
+                let trial_time: Vec<Option<Decimal>> = times(t)
+                    .into_iter()
+                    .map(|time| time.map(|time| time - t.time_record.start))
+                    .collect();
+                df.with_column(Series::new(
+                    "trial_time",
+                    maybe_decimal_to_arrow_decimal(trial_time),
+                ))?;
+
+                if df.column("trial_id").is_err() {
+                    df.insert_at_idx(0, Series::new("trial_id", vec![t.id; n]))?;
+                }
+
+                for (i, label) in self.variable_labels.iter().enumerate() {
+                    let name = if df.column(label).is_ok() {
+                        format!("var_{label}")
+                    } else {
+                        label.clone()
+                    };
+                    let value = t.variables.get(i).map(|v| v.as_str());
+                    df.with_column(Series::new(&name, vec![value; n]))?;
+                }
+                Ok(df)
+            })
+            .collect::<PolarsResult<_>>()?;
+        concat_aligned(frames)
+    }
+
+    /// Samples of all trials, see [`Experiment::long_format`] for the added columns.
+    pub fn samples(&self) -> PolarsResult<DataFrame> {
+        self.long_format(Trial::samples, |t| {
+            t.samples.iter().map(|s| Some(s.time)).collect()
+        })
+    }
+
+    /// Events of all trials, in the same layout as [`Trial::events`].
+    pub fn events(&self) -> PolarsResult<DataFrame> {
+        self.long_format(Trial::events, |t| {
+            t.events.iter().map(|e| Some(e.time_record.start)).collect()
+        })
+    }
+
+    pub fn raw_samples(&self) -> PolarsResult<DataFrame> {
+        self.long_format(Trial::raw_samples, |t| {
+            t.raw_samples.iter().map(|s| Some(s.time)).collect()
+        })
+    }
+
+    pub fn targets(&self) -> PolarsResult<DataFrame> {
+        self.long_format(Trial::targets, |t| {
+            let mut names: Vec<&String> = t.targets.keys().collect();
+            names.sort();
+            names
+                .into_iter()
+                .flat_map(|name| t.targets[name].iter().map(|info| Some(info.time)))
+                .collect()
+        })
+    }
+
+    pub fn cam_frames(&self) -> PolarsResult<DataFrame> {
+        self.long_format(Trial::cam_frames, |t| {
+            t.camera_frames.iter().map(|f| f.eyelink_time).collect()
+        })
+    }
+
+    /// Data quality report with one row per trial and recorded eye.
+    pub fn data_quality(&self) -> PolarsResult<DataFrame> {
+        quality_frame(&self.quality_report())
+    }
+
+    /// Writes the trial, sample, event, raw sample, target and camera frame tables to
+    /// `<stem>.<table>.<ext>` files next to `path`, returning the written paths. `format` is
+    /// [`OutputFormat::Parquet`] or [`OutputFormat::Csv`].
+    pub fn write_tables(&self, path: &Path, format: OutputFormat) -> anyhow::Result<Vec<PathBuf>> {
+        if !matches!(format, OutputFormat::Parquet | OutputFormat::Csv) {
+            bail!("{format:?} is not a table format");
+        }
+        let tables = [
+            ("trials", self.trial_variables()?),
+            ("samples", self.samples()?),
+            ("events", self.events()?),
+            ("raw_samples", self.raw_samples()?),
+            ("targets", self.targets()?),
+            ("cam_frames", self.cam_frames()?),
+        ];
+
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| anyhow!("invalid output path: {}", path.display()))?;
+        let mut paths = Vec::new();
+        for (name, mut df) in tables {
+            let table_path = path.with_file_name(format!("{stem}.{name}.{}", format.extension()));
+            let file = File::create(&table_path)?;
+            if format == OutputFormat::Parquet {
+                ParquetWriter::new(file).finish(&mut df)?;
+            } else {
+                // CSV has no decimal type, write those columns as floats
+                let columns = df
+                    .get_columns()
+                    .iter()
+                    .map(|s| match s.dtype() {
+                        DataType::Decimal(_, _) => s.cast(&DataType::Float64),
+                        _ => Ok(s.clone()),
+                    })
+                    .collect::<PolarsResult<Vec<_>>>()?;
+                CsvWriter::new(file).finish(&mut DataFrame::new(columns)?)?;
+            }
+            paths.push(table_path);
+        }
+        Ok(paths)
+    }
+}
+
+/// Type a column takes when stacking frames in which it has types `a` and `b`. Columns without
+/// any value have the null type and take the other one, decimals take the larger scale.
+fn common_dtype(a: &DataType, b: &DataType) -> PolarsResult<DataType> {
+    match (a, b) {
+        // Decimal types compare equal regardless of their scale
+        (DataType::Decimal(_, s1), DataType::Decimal(_, s2)) => {
+            Ok(DataType::Decimal(None, (*s1).max(*s2)))
+        }
+        _ if a == b => Ok(a.clone()),
+        (DataType::Null, other) | (other, DataType::Null) => Ok(other.clone()),
+        _ => Err(PolarsError::ComputeError(
+            format!("Cannot stack columns of type {a} and {b}").into(),
+        )),
+    }
+}
+
+/// Stacks frames whose columns may differ in presence and type. Every column is cast to the
+/// type common to all frames, missing columns are filled with nulls.
+pub(crate) fn concat_aligned(frames: Vec<DataFrame>) -> PolarsResult<DataFrame> {
+    let mut schema: Vec<(String, DataType)> = Vec::new();
+    for s in frames.iter().flat_map(|df| df.get_columns()) {
+        match schema.iter_mut().find(|(name, _)| name == s.name()) {
+            Some((_, dtype)) => *dtype = common_dtype(dtype, s.dtype())?,
+            None => schema.push((s.name().to_string(), s.dtype().clone())),
+        }
+    }
+
+    let mut stacked: Option<DataFrame> = None;
+    for df in frames {
+        let columns = schema
+            .iter()
+            .map(|(name, dtype)| match df.column(name) {
+                Ok(s) => s.cast(dtype),
+                Err(_) => Ok(Series::full_null(name, df.height(), dtype)),
+            })
+            .collect::<PolarsResult<Vec<_>>>()?;
+        let df = DataFrame::new(columns)?;
+        match &mut stacked {
+            Some(stacked) => {
+                stacked.vstack_mut(&df)?;
+            }
+            None => stacked = Some(df),
+        }
+    }
+    let mut df = stacked.unwrap_or_else(DataFrame::empty);
+    df.align_chunks();
+    Ok(df)
+}
+
+pub fn quality_frame(report: &[TrialQuality]) -> PolarsResult<DataFrame> {
+    let mut ls_trial_id = Vec::new();
+    let mut ls_eye = Vec::new();
+    let mut ls_n_samples = Vec::new();
+    let mut ls_duration = Vec::new();
+    let mut ls_sampling_rate = Vec::new();
+    let mut ls_configured_rate = Vec::new();
+    let mut ls_missing_gaze = Vec::new();
+    let mut ls_cr_missing = Vec::new();
+    let mut ls_cr_recovering = Vec::new();
+    let mut ls_interpolated = Vec::new();
+    let mut ls_longest_gap = Vec::new();
+    let mut ls_blink_count = Vec::new();
+    let mut ls_blink_rate = Vec::new();
+
+    for t in report {
+        for e in &t.eyes {
+            ls_trial_id.push(t.trial_id);
+            ls_eye.push(e.eye.name());
+            ls_n_samples.push(t.n_samples as u64);
+            ls_duration.push(t.duration);
+            ls_sampling_rate.push(t.sampling_rate);
+            ls_configured_rate.push(t.configured_rate);
+            ls_missing_gaze.push(e.missing_gaze);
+            ls_cr_missing.push(e.cr_missing);
+            ls_cr_recovering.push(e.cr_recovering);
+            ls_interpolated.push(e.interpolated);
+            ls_longest_gap.push(e.longest_gap);
+            ls_blink_count.push(e.blink_count as u64);
+            ls_blink_rate.push(e.blink_rate);
+        }
+    }
+
+    df! [
+        "trial_id" => ls_trial_id,
+        "eye" => ls_eye,
+        "n_samples" => ls_n_samples,
+        "duration" => ls_duration,
+        "sampling_rate" => ls_sampling_rate,
+        "configured_rate" => ls_configured_rate,
+        "missing_gaze" => ls_missing_gaze,
+        "cr_missing" => ls_cr_missing,
+        "cr_recovering" => ls_cr_recovering,
+        "interpolated" => ls_interpolated,
+        "longest_gap" => ls_longest_gap,
+        "blink_count" => ls_blink_count,
+        "blink_rate" => ls_blink_rate,
+    ]
 }
 
 // pub fn decimal_to_f64(input: Vec<Decimal>) -> Vec<f64> {
@@ -114,22 +347,36 @@ impl Trial {
         let mut ls_left_pos_x = Vec::new();
         let mut ls_left_pos_y = Vec::new();
         let mut ls_left_area = Vec::new();
+        let mut ls_left_vel_x = Vec::new();
+        let mut ls_left_vel_y = Vec::new();
+        let mut ls_left_cr = Vec::new();
         let mut ls_right_pos_x = Vec::new();
         let mut ls_right_pos_y = Vec::new();
         let mut ls_right_area = Vec::new();
+        let mut ls_right_vel_x = Vec::new();
+        let mut ls_right_vel_y = Vec::new();
+        let mut ls_right_cr = Vec::new();
         let mut ls_res_x = Vec::new();
         let mut ls_res_y = Vec::new();
+        let mut ls_interpolated = Vec::new();
 
         for s in &self.samples {
             ls_time.push(s.time);
             ls_left_pos_x.push(s.left.as_ref().map(|e| e.position[0]));
             ls_left_pos_y.push(s.left.as_ref().map(|e| e.position[1]));
             ls_left_area.push(s.left.as_ref().map(|e| e.area));
+            ls_left_vel_x.push(s.left.as_ref().and_then(|e| e.velocity).map(|v| v[0]));
+            ls_left_vel_y.push(s.left.as_ref().and_then(|e| e.velocity).map(|v| v[1]));
+            ls_left_cr.push(s.left.as_ref().map(|e| e.cr.name()));
             ls_right_pos_x.push(s.right.as_ref().map(|e| e.position[0]));
             ls_right_pos_y.push(s.right.as_ref().map(|e| e.position[1]));
             ls_right_area.push(s.right.as_ref().map(|e| e.area));
+            ls_right_vel_x.push(s.right.as_ref().and_then(|e| e.velocity).map(|v| v[0]));
+            ls_right_vel_y.push(s.right.as_ref().and_then(|e| e.velocity).map(|v| v[1]));
+            ls_right_cr.push(s.right.as_ref().map(|e| e.cr.name()));
             ls_res_x.push(s.resolution.as_ref().map(|r| r[0]));
             ls_res_y.push(s.resolution.as_ref().map(|r| r[1]));
+            ls_interpolated.push(s.interpolated);
         }
 
         df! [
@@ -140,8 +387,87 @@ impl Trial {
             "right_pos_y" => maybe_decimal_to_arrow_decimal(ls_right_pos_y),
             "left_area" => maybe_decimal_to_arrow_decimal(ls_left_area),
             "right_area" => maybe_decimal_to_arrow_decimal(ls_right_area),
+            "left_vel_x" => maybe_decimal_to_arrow_decimal(ls_left_vel_x),
+            "left_vel_y" => maybe_decimal_to_arrow_decimal(ls_left_vel_y),
+            "right_vel_x" => maybe_decimal_to_arrow_decimal(ls_right_vel_x),
+            "right_vel_y" => maybe_decimal_to_arrow_decimal(ls_right_vel_y),
+            "left_cr" => ls_left_cr,
+            "right_cr" => ls_right_cr,
             "res_x" => maybe_decimal_to_arrow_decimal(ls_res_x),
             "res_y" => maybe_decimal_to_arrow_decimal(ls_res_y),
+            "interpolated" => ls_interpolated,
+        ]
+    }
+
+    pub fn raw_samples(&self) -> PolarsResult<DataFrame> {
+        let mut ls_time = Vec::new();
+        let mut columns: Vec<(String, Vec<Decimal>)> = ["left", "right"]
+            .iter()
+            .flat_map(|eye| {
+                [
+                    "pupil_pos_x",
+                    "pupil_pos_y",
+                    "pupil_area",
+                    "pupil_size_x",
+                    "pupil_size_y",
+                    "cr_pos_x",
+                    "cr_pos_y",
+                    "cr_area",
+                ]
+                .map(|name| (format!("{eye}_{name}"), Vec::new()))
+            })
+            .collect();
+
+        for s in &self.raw_samples {
+            ls_time.push(s.time);
+            let values = [&s.left, &s.right].into_iter().flat_map(|e| {
+                [
+                    e.pupil_position[0],
+                    e.pupil_position[1],
+                    e.pupil_area,
+                    e.pupil_size[0],
+                    e.pupil_size[1],
+                    e.cr_position[0],
+                    e.cr_position[1],
+                    e.cr_area,
+                ]
+            });
+            for ((_, list), v) in columns.iter_mut().zip(values) {
+                list.push(v);
+            }
+        }
+
+        let mut series = vec![Series::new("time", decimal_to_arrow_decimal(ls_time))];
+        series.extend(
+            columns
+                .into_iter()
+                .map(|(name, list)| Series::new(&name, decimal_to_arrow_decimal(list))),
+        );
+        DataFrame::new(series)
+    }
+
+    pub fn targets(&self) -> PolarsResult<DataFrame> {
+        let mut ls_name = Vec::new();
+        let mut ls_time = Vec::new();
+        let mut ls_x = Vec::new();
+        let mut ls_y = Vec::new();
+
+        let mut names: Vec<&String> = self.targets.keys().collect();
+        names.sort();
+        for name in names {
+            for t in &self.targets[name] {
+                ls_name.push(name.as_str());
+                ls_time.push(t.time);
+                ls_x.push(t.position[0]);
+                ls_y.push(t.position[1]);
+            }
+        }
+
+        df! [
+            "name" => ls_name,
+            "time" => decimal_to_arrow_decimal(ls_time),
+            "x" => ls_x,
+            "y" => ls_y,
         ]
     }
 
@@ -168,8 +494,112 @@ impl Trial {
             "cam_time" => ls_cam_time,
             "sys_time" => ls_sys_time,
             "process_time" => decimal_to_arrow_decimal(ls_process_time),
-            "eyelink_time" => decimal_to_arrow_decimal(ls_eyelink_time),
+            "eyelink_time" => maybe_decimal_to_arrow_decimal(ls_eyelink_time),
+
+        ]
+    }
+
+    /// One row per event, with nulls for fields that do not apply to the event kind.
+    pub fn events(&self) -> PolarsResult<DataFrame> {
+        let mut columns = EventColumns::default();
+        for e in &self.events {
+            columns.push(self.id, e);
+        }
+        columns.finish()
+    }
+}
+
+#[derive(Default)]
+struct EventColumns {
+    trial_id: Vec<u32>,
+    eye: Vec<&'static str>,
+    kind: Vec<&'static str>,
+    start: Vec<Decimal>,
+    end: Vec<Decimal>,
+    duration: Vec<Decimal>,
+    res_x: Vec<Option<Decimal>>,
+    res_y: Vec<Option<Decimal>>,
+    average_pos_x: Vec<Option<Decimal>>,
+    average_pos_y: Vec<Option<Decimal>>,
+    average_pupil_area: Vec<Option<Decimal>>,
+    start_pos_x: Vec<Option<Decimal>>,
+    start_pos_y: Vec<Option<Decimal>>,
+    end_pos_x: Vec<Option<Decimal>>,
+    end_pos_y: Vec<Option<Decimal>>,
+    amplitude: Vec<Option<f64>>,
+    angle: Vec<Option<f64>>,
+    tracker_amplitude: Vec<Option<Decimal>>,
+    peak_velocity: Vec<Option<Decimal>>,
+}
+
+impl EventColumns {
+    fn push(&mut self, trial_id: u32, e: &EventRecord) {
+        self.trial_id.push(trial_id);
+        self.eye.push(e.eye.name());
+        self.kind.push(e.info.kind());
+        self.start.push(e.time_record.start);
+        self.end.push(e.time_record.end);
+        self.duration.push(e.time_record.end - e.time_record.start);
+        self.res_x.push(e.resolution.map(|r| r[0]));
+        self.res_y.push(e.resolution.map(|r| r[1]));
+        self.amplitude.push(e.saccade_amplitude());
+        self.angle.push(e.saccade_angle());
+
+        let (average_position, average_pupil_area) = match e.info {
+            EventInfo::Fixation {
+                average_position,
+                average_pupil_area,
+            } => (Some(average_position), Some(average_pupil_area)),
+            _ => (None, None),
+        };
+        self.average_pos_x.push(average_position.map(|p| p[0]));
+        self.average_pos_y.push(average_position.map(|p| p[1]));
+        self.average_pupil_area.push(average_pupil_area);
+
+        let (start_position, end_position, movement_angle, peak_velocity) = match e.info {
+            EventInfo::Saccade {
+                start_position,
+                end_position,
+                movement_angle,
+                peak_velocity,
+            } => (
+                start_position,
+                end_position,
+                movement_angle,
+                Some(peak_velocity),
+            ),
+            _ => (None, None, None, None),
+        };
+        self.start_pos_x.push(start_position.map(|p| p[0]));
+        self.start_pos_y.push(start_position.map(|p| p[1]));
+        self.end_pos_x.push(end_position.map(|p| p[0]));
+        self.end_pos_y.push(end_position.map(|p| p[1]));
+        // The amplitude in degrees EyeLink reports with the saccade
+        self.tracker_amplitude.push(movement_angle);
+        self.peak_velocity.push(peak_velocity);
+    }
 
+    fn finish(self) -> PolarsResult<DataFrame> {
+        df! [
+            "trial_id" => self.trial_id,
+            "eye" => self.eye,
+            "kind" => self.kind,
+            "start" => decimal_to_arrow_decimal(self.start),
+            "end" => decimal_to_arrow_decimal(self.end),
+            "duration" => decimal_to_arrow_decimal(self.duration),
+            "res_x" => maybe_decimal_to_arrow_decimal(self.res_x),
+            "res_y" => maybe_decimal_to_arrow_decimal(self.res_y),
+            "average_pos_x" => maybe_decimal_to_arrow_decimal(self.average_pos_x),
+            "average_pos_y" => maybe_decimal_to_arrow_decimal(self.average_pos_y),
+            "average_pupil_area" => maybe_decimal_to_arrow_decimal(self.average_pupil_area),
+            "start_pos_x" => maybe_decimal_to_arrow_decimal(self.start_pos_x),
+            "start_pos_y" => maybe_decimal_to_arrow_decimal(self.start_pos_y),
+            "end_pos_x" => maybe_decimal_to_arrow_decimal(self.end_pos_x),
+            "end_pos_y" => maybe_decimal_to_arrow_decimal(self.end_pos_y),
+            "amplitude" => self.amplitude,
+            "angle" => self.angle,
+            "tracker_amplitude" => maybe_decimal_to_arrow_decimal(self.tracker_amplitude),
+            "peak_velocity" => maybe_decimal_to_arrow_decimal(self.peak_velocity),
         ]
     }
 }
@@ -177,9 +607,136 @@ impl Trial {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::common::Eye;
+    use crate::generic::{CRStatus, EyeSampleData, MetaData, Sample, TimeRecord};
     use crate::Decimal;
     use std::str::FromStr;
 
+    fn sample(time: &str, left: bool) -> Sample {
+        let data = EyeSampleData {
+            position: [Decimal::from_str("512.5").unwrap(), Decimal::from(384)],
+            area: Decimal::from(1000),
+            velocity: None,
+            cr: CRStatus::Found,
+        };
+        Sample {
+            time: Decimal::from_str(time).unwrap(),
+            left: left.then_some(data),
+            right: (!left).then_some(data),
+            resolution: None,
+            interpolated: false,
+        }
+    }
+
+    fn time_record(start: i64) -> TimeRecord {
+        TimeRecord {
+            start: Decimal::from(start),
+            end: Decimal::from(start + 1000),
+        }
+    }
+
+    #[test]
+    fn test_long_format_differing_trials() {
+        let blink = EventRecord {
+            time_record: TimeRecord {
+                start: Decimal::from(2000),
+                end: Decimal::from(2100),
+            },
+            eye: Eye::Right,
+            resolution: None,
+            info: EventInfo::Blink,
+        };
+        let fixation = EventRecord {
+            info: EventInfo::Fixation {
+                average_position: [Decimal::from_str("1.25").unwrap(), Decimal::from(2)],
+                average_pupil_area: Decimal::from(900),
+            },
+            resolution: Some([Decimal::from(30), Decimal::from(30)]),
+            ..blink
+        };
+        let first = Trial {
+            samples: vec![sample("1000", true), sample("1001", true)],
+            variables: vec!["a".to_string(), "x".to_string()],
+            ..Trial::empty(1, time_record(1000))
+        };
+        // Only the right eye, times with a different decimal scale and one variable less
+        let second = Trial {
+            samples: vec![sample("2000.5", false)],
+            events: vec![blink, fixation],
+            variables: vec!["b".to_string()],
+            ..Trial::empty(2, time_record(2000))
+        };
+        // Only events without any optional field
+        let third = Trial {
+            events: vec![blink],
+            ..Trial::empty(3, time_record(3000))
+        };
+        let exp = Experiment {
+            meta: MetaData::default(),
+            variable_labels: vec!["condition".to_string(), "block".to_string()],
+            trials: vec![first, second, third],
+        };
+
+        let samples = exp.samples().unwrap();
+        assert_eq!(samples.height(), 3);
+        assert!(matches!(
+            samples.column("left_pos_x").unwrap().dtype(),
+            DataType::Decimal(_, Some(1))
+        ));
+        assert_eq!(samples.column("left_pos_x").unwrap().null_count(), 1);
+        assert_eq!(samples.column("right_pos_x").unwrap().null_count(), 2);
+        let trial_time = samples.column("trial_time").unwrap();
+        let trial_time = trial_time.cast(&DataType::Float64).unwrap();
+        assert_eq!(
+            trial_time.f64().unwrap().to_vec(),
+            [Some(0.), Some(1.), Some(0.5)]
+        );
+        let block = samples.column("block").unwrap();
+        assert_eq!(block.get(0).unwrap(), AnyValue::Utf8("x"));
+        assert_eq!(block.get(2).unwrap(), AnyValue::Null);
+
+        let events = exp.events().unwrap();
+        assert_eq!(events.height(), 3);
+        assert!(matches!(
+            events.column("average_pos_x").unwrap().dtype(),
+            DataType::Decimal(_, Some(2))
+        ));
+        assert_eq!(events.column("res_x").unwrap().null_count(), 2);
+    }
+
+    #[test]
+    fn test_saccade_columns() {
+        let saccade = |start_position, end_position| EventRecord {
+            time_record: TimeRecord {
+                start: Decimal::from(1000),
+                end: Decimal::from(1040),
+            },
+            eye: Eye::Left,
+            resolution: Some([Decimal::from(10), Decimal::from(10)]),
+            info: EventInfo::Saccade {
+                start_position,
+                end_position,
+                movement_angle: Some(Decimal::from_str("4.24").unwrap()),
+                peak_velocity: Decimal::from(250),
+            },
+        };
+        let start = [Decimal::from(100), Decimal::from(100)];
+        let end = [Decimal::from(130), Decimal::from(70)];
+        let trial = Trial {
+            events: vec![saccade(Some(start), Some(end)), saccade(None, Some(end))],
+            ..Trial::empty(1, time_record(1000))
+        };
+
+        let events = trial.events().unwrap();
+        let angle = events.column("angle").unwrap().f64().unwrap().to_vec();
+        assert!((angle[0].unwrap() + 45.).abs() < 1e-9);
+        assert_eq!(angle[1], None);
+        let amplitude = events.column("tracker_amplitude").unwrap();
+        let amplitude = amplitude.cast(&DataType::Float64).unwrap();
+        assert_eq!(amplitude.f64().unwrap().to_vec(), [Some(4.24), Some(4.24)]);
+        assert!(events.column("movement_angle").is_err());
+    }
+
     #[test]
     fn test_decimal_conversion() {
         let d = Decimal::from_str("12.23").unwrap();
@@ -0,0 +1,265 @@
+//! Merging experiments recorded in several files and splitting experiments into parts.
+
+use crate::decimal_to_f64;
+use crate::generic::{Experiment, Trial};
+use anyhow::bail;
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+/// Time range of the trials of `exp` in ms, from the earliest start to the latest end.
+fn time_range(exp: &Experiment) -> Option<(f64, f64)> {
+    exp.trials.iter().fold(None, |range, t| {
+        let (start, end) = (
+            decimal_to_f64(t.time_record.start),
+            decimal_to_f64(t.time_record.end),
+        );
+        Some(match range {
+            Some((s, e)) => (f64::min(s, start), f64::max(e, end)),
+            None => (start, end),
+        })
+    })
+}
+
+impl Experiment {
+    /// Copy of the metadata and variable labels with the given trials.
+    fn with_trials(&self, trials: Vec<Trial>) -> Experiment {
+        Experiment {
+            meta: self.meta.clone(),
+            variable_labels: self.variable_labels.clone(),
+            trials,
+        }
+    }
+
+    /// Concatenates experiments, e.g. a session split over several files, keeping the metadata
+    /// of the first part.
+    ///
+    /// The variable labels of all parts are combined, variables a part does not define are left
+    /// empty. Trials with an id already used by an earlier trial get the next free id. Parts
+    /// whose trials overlap in time are rejected unless `allow_overlap` is set, as they are
+    /// usually the same recording loaded twice.
+    pub fn merge(parts: Vec<Experiment>, allow_overlap: bool) -> anyhow::Result<Experiment> {
+        let mut parts = parts.into_iter();
+        let Some(first) = parts.next() else {
+            bail!("No experiments to merge");
+        };
+        let mut merged = first.with_trials(Vec::new());
+        let mut ranges = Vec::new();
+        let mut ids = HashSet::new();
+        let mut next_id = 0;
+
+        for (i, part) in std::iter::once(first).chain(parts).enumerate() {
+            if let Some((start, end)) = time_range(&part) {
+                if !allow_overlap {
+                    if let Some(j) = ranges.iter().position(|&(s, e)| start < e && s < end) {
+                        bail!("Trials of part {i} overlap in time with those of part {j}");
+                    }
+                }
+                ranges.push((start, end));
+            }
+
+            for label in &part.variable_labels {
+                if !merged.variable_labels.contains(label) {
+                    merged.variable_labels.push(label.clone());
+                }
+            }
+            // Position of each merged label in the variables of this part
+            let mapping: Vec<Option<usize>> = merged
+                .variable_labels
+                .iter()
+                .map(|label| part.variable_index(label))
+                .collect();
+
+            for mut trial in part.trials {
+                trial.variables = mapping
+                    .iter()
+                    .map(|i| {
+                        i.and_then(|i| trial.variables.get(i).cloned())
+                            .unwrap_or_default()
+                    })
+                    .collect();
+                next_id = next_id.max(trial.id + 1);
+                if !ids.insert(trial.id) {
+                    trial.id = next_id;
+                    ids.insert(trial.id);
+                    next_id += 1;
+                }
+                merged.trials.push(trial);
+            }
+        }
+        // Labels are only appended, so trials of earlier parts just lack the later variables
+        let n = merged.variable_labels.len();
+        for trial in &mut merged.trials {
+            trial.variables.resize(n, String::new());
+        }
+        Ok(merged)
+    }
+
+    /// Splits the experiment into one part per range of trial ids. Trials outside all ranges are
+    /// dropped, trials in several ranges are in each of the parts.
+    pub fn split_by_trial_ids(&self, ranges: &[RangeInclusive<u32>]) -> Vec<Experiment> {
+        ranges
+            .iter()
+            .map(|range| {
+                let trials = self
+                    .trials
+                    .iter()
+                    .filter(|t| range.contains(&t.id))
+                    .cloned()
+                    .collect();
+                self.with_trials(trials)
+            })
+            .collect()
+    }
+
+    /// Splits the experiment into one part per value of the trial variable `label`, in order of
+    /// first appearance.
+    pub fn split_by_variable(&self, label: &str) -> anyhow::Result<Vec<(String, Experiment)>> {
+        let Some(index) = self.variable_index(label) else {
+            bail!("Unknown trial variable {label}");
+        };
+        let mut parts: Vec<(String, Experiment)> = Vec::new();
+        for trial in &self.trials {
+            let value = trial.variables.get(index).cloned().unwrap_or_default();
+            match parts.iter_mut().find(|(v, _)| *v == value) {
+                Some((_, part)) => part.trials.push(trial.clone()),
+                None => parts.push((value, self.with_trials(vec![trial.clone()]))),
+            }
+        }
+        Ok(parts)
+    }
+
+    /// Splits the experiment where recording was interrupted between consecutive trials, that
+    /// is where the last sample of a trial and the first sample of the next one are more than
+    /// `max_gap` ms apart. Trials without samples stay with the preceding trial.
+    pub fn split_by_recording_blocks(&self, max_gap: f64) -> Vec<Experiment> {
+        let mut parts: Vec<Vec<Trial>> = Vec::new();
+        let mut last_sample = None;
+        for trial in &self.trials {
+            let first = trial.samples.first().map(|s| decimal_to_f64(s.time));
+            let interrupted = match (last_sample, first) {
+                (Some(last), Some(first)) => first - last > max_gap,
+                _ => false,
+            };
+            match parts.last_mut() {
+                Some(part) if !interrupted => part.push(trial.clone()),
+                _ => parts.push(vec![trial.clone()]),
+            }
+            if let Some(sample) = trial.samples.last() {
+                last_sample = Some(decimal_to_f64(sample.time));
+            }
+        }
+        parts
+            .into_iter()
+            .map(|trials| self.with_trials(trials))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generic::{Experiment, MetaData, Sample, TimeRecord, Trial};
+    use crate::Decimal;
+
+    /// Ten samples, one per millisecond from `start`.
+    fn samples(start: u32) -> Vec<Sample> {
+        (start..start + 10)
+            .map(|t| Sample {
+                time: Decimal::from(t),
+                left: None,
+                right: None,
+                resolution: None,
+                interpolated: false,
+            })
+            .collect()
+    }
+
+    fn time_record(start: u32) -> TimeRecord {
+        TimeRecord {
+            start: Decimal::from(start),
+            end: Decimal::from(start + 9),
+        }
+    }
+
+    fn variables(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    fn experiment(labels: &[&str], trials: Vec<Trial>) -> Experiment {
+        Experiment {
+            meta: MetaData::default(),
+            variable_labels: labels.iter().map(|l| l.to_string()).collect(),
+            trials,
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let a = experiment(
+            &["block", "cond"],
+            vec![
+                Trial {
+                    variables: variables(&["1", "a"]),
+                    ..Trial::empty(1, time_record(0))
+                },
+                Trial {
+                    variables: variables(&["1", "b"]),
+                    ..Trial::empty(2, time_record(10))
+                },
+            ],
+        );
+        let b = experiment(
+            &["cond", "rt"],
+            vec![
+                Trial {
+                    variables: variables(&["c", "300"]),
+                    ..Trial::empty(1, time_record(100))
+                },
+                Trial {
+                    variables: variables(&["d", "250"]),
+                    ..Trial::empty(3, time_record(110))
+                },
+            ],
+        );
+        let merged = Experiment::merge(vec![a.clone(), b], false).unwrap();
+        assert_eq!(merged.variable_labels, ["block", "cond", "rt"]);
+        let ids: Vec<u32> = merged.trials.iter().map(|t| t.id).collect();
+        assert_eq!(ids, [1, 2, 3, 4]);
+        assert_eq!(merged.trials[2].variables, ["", "c", "300"]);
+        assert_eq!(merged.trials[0].variables, ["1", "a", ""]);
+
+        assert!(Experiment::merge(vec![a.clone(), a.clone()], false).is_err());
+        assert_eq!(
+            Experiment::merge(vec![a.clone(), a], true)
+                .unwrap()
+                .trials
+                .len(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_split() {
+        let exp = experiment(
+            &["block"],
+            [(1, 0, "1"), (2, 10, "1"), (3, 100, "2"), (4, 110, "2")]
+                .into_iter()
+                .map(|(id, start, block)| Trial {
+                    samples: samples(start),
+                    variables: variables(&[block]),
+                    ..Trial::empty(id, time_record(start))
+                })
+                .collect(),
+        );
+        let parts = exp.split_by_trial_ids(&[1..=1, 2..=4]);
+        assert_eq!(parts[1].trials.len(), 3);
+
+        let parts = exp.split_by_variable("block").unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[1].0, "2");
+        assert!(exp.split_by_variable("missing").is_err());
+
+        let parts = exp.split_by_recording_blocks(5.);
+        let sizes: Vec<usize> = parts.iter().map(|p| p.trials.len()).collect();
+        assert_eq!(sizes, [2, 2]);
+    }
+}
@@ -1,6 +1,10 @@
 mod helpers;
+mod merge;
 
-use crate::asc::{CameraFrameVersion, Element, MsgType, PreambleMsg, RawSampleMsg, TrialData};
+use crate::asc::{
+    CameraFrameVersion, Element, EyeSpecification, MsgType, PreambleMsg, RawSampleMsg,
+    TrackingAlgorithm, TrackingMode, TrialData,
+};
 use crate::common::Eye;
 use crate::{Decimal, NaiveDateTime};
 use anyhow::anyhow;
@@ -31,6 +35,24 @@ pub struct Experiment {
 pub struct MetaData {
     pub recording_datetime: NaiveDateTime,
     pub preamble_lines: Vec<String>,
+    /// Sampling rate in Hz from the first recording configuration (`RECCFG`)
+    #[serde(default)]
+    pub sampling_rate: Option<Decimal>,
+    /// Eyes recorded according to the first recording configuration
+    #[serde(default)]
+    pub recorded_eyes: Vec<Eye>,
+    /// Screen bounds (left, top, right, bottom) of the gaze coordinates (`GAZE_COORDS`)
+    #[serde(default)]
+    pub gaze_coords: Option<[Decimal; 4]>,
+    /// `P` (pupil only) or `CR` (pupil-corneal reflection) from the recording configuration
+    #[serde(default)]
+    pub tracking_mode: Option<String>,
+    /// `ellipse` or `centroid` pupil fitting
+    #[serde(default)]
+    pub pupil_fit_method: Option<String>,
+    /// Calibration type of the first calibration, e.g. `HV9`
+    #[serde(default)]
+    pub calibration_type: Option<String>,
 }
 
 #[derive(
@@ -65,6 +87,20 @@ pub struct Trial {
     pub camera_frames: Vec<CameraFrame>,
     pub variables: Vec<String>,
     pub targets: HashMap<String, Vec<TargetInfo>>,
+    /// Messages between the start and the result of the trial not parsed into any of the other
+    /// fields
+    #[serde(default)]
+    pub messages: Vec<Message>,
+}
+
+#[derive(
+    rkyv::Archive, rkyv::Serialize, rkyv::Deserialize, Serialize, Deserialize, Debug, Clone,
+)]
+#[archive(check_bytes)]
+#[cfg_attr(feature = "py-ext", pyclass(get_all))]
+pub struct Message {
+    pub time: Decimal,
+    pub text: String,
 }
 
 #[derive(
@@ -73,8 +109,8 @@ pub struct Trial {
 #[archive(check_bytes)]
 #[cfg_attr(feature = "py-ext", pyclass(get_all))]
 pub struct TargetInfo {
-    time: Decimal,
-    position: [i32; 2],
+    pub time: Decimal,
+    pub position: [i32; 2],
 }
 
 #[derive(
@@ -83,10 +119,10 @@ pub struct TargetInfo {
 #[archive(check_bytes)]
 #[cfg_attr(feature = "py-ext", pyclass(get_all))]
 pub struct EventRecord {
-    time_record: TimeRecord,
-    eye: Eye,
-    resolution: Option<Vector>,
-    info: EventInfo,
+    pub time_record: TimeRecord,
+    pub eye: Eye,
+    pub resolution: Option<Vector>,
+    pub info: EventInfo,
 }
 
 #[derive(
@@ -101,6 +137,7 @@ pub enum EventInfo {
     Saccade {
         start_position: Option<Position>,
         end_position: Option<Position>,
+        /// Amplitude in degrees reported by the tracker (`ampl` of `ESACC`), not an angle
         movement_angle: Option<Decimal>,
         peak_velocity: Decimal,
     },
@@ -117,6 +154,8 @@ pub struct Sample {
     pub left: Option<EyeSampleData>,
     pub right: Option<EyeSampleData>,
     pub resolution: Option<Vector>,
+    #[serde(default)]
+    pub interpolated: bool,
 }
 
 #[derive(
@@ -184,6 +223,13 @@ pub enum CRStatus {
     Found,
 }
 
+impl Experiment {
+    /// Position of a trial variable in `variable_labels` and in each trial's `variables`.
+    pub fn variable_index(&self, label: &str) -> Option<usize> {
+        self.variable_labels.iter().position(|l| l == label)
+    }
+}
+
 impl TimeRecord {
     pub fn new_checked(start: Decimal, end: Decimal, duration: Decimal) -> anyhow::Result<Self> {
         if end - start == duration {
@@ -203,6 +249,16 @@ fn optional_pos(x: Option<Decimal>, y: Option<Decimal>) -> Option<Position> {
     x.and_then(|x| y.map(|y| [x, y]))
 }
 
+impl EventInfo {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EventInfo::Fixation { .. } => "fixation",
+            EventInfo::Saccade { .. } => "saccade",
+            EventInfo::Blink => "blink",
+        }
+    }
+}
+
 impl EventRecord {
     fn from_event_info(
         eye: Eye,
@@ -294,11 +350,42 @@ impl Trial {
             events: Vec::new(),
             variables: Vec::new(),
             targets: HashMap::new(),
+            messages: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Trial {
+    /// Trial without any data, to fill in the other fields of test trials with struct update
+    /// syntax.
+    pub(crate) fn empty(id: u32, time_record: TimeRecord) -> Self {
+        Trial {
+            time_record,
+            ..Trial::from_trial_start(id, time_record.start)
+        }
+    }
+}
+
+impl From<EyeSpecification> for Vec<Eye> {
+    fn from(value: EyeSpecification) -> Self {
+        match value {
+            EyeSpecification::L => vec![Eye::Left],
+            EyeSpecification::R => vec![Eye::Right],
+            EyeSpecification::LR => vec![Eye::Left, Eye::Right],
         }
     }
 }
 
 impl CRStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CRStatus::Missing => "missing",
+            CRStatus::Recovering => "recovering",
+            CRStatus::Found => "found",
+        }
+    }
+
     pub fn from_asc(cr_missing: bool, cr_recovering: bool) -> Self {
         if cr_missing {
             CRStatus::Missing
@@ -349,6 +436,13 @@ impl RawSample {
             right: RawEyeSampleData::from_asc(right),
         }
     }
+
+    pub fn eye(&self, eye: Eye) -> &RawEyeSampleData {
+        match eye {
+            Eye::Left => &self.left,
+            Eye::Right => &self.right,
+        }
+    }
 }
 
 impl CameraFrame {
@@ -374,6 +468,13 @@ impl CameraFrame {
 }
 
 impl Sample {
+    pub fn eye(&self, eye: Eye) -> Option<&EyeSampleData> {
+        match eye {
+            Eye::Left => self.left.as_ref(),
+            Eye::Right => self.right.as_ref(),
+        }
+    }
+
     pub fn from_asc(
         time: Decimal,
         left_pos_x: Option<Decimal>,
@@ -389,7 +490,7 @@ impl Sample {
         res_x: Option<Decimal>,
         res_y: Option<Decimal>,
         _unknown: Option<Decimal>,
-        _interpolated: bool,
+        interpolated: bool,
         left_cr_missing: bool,
         left_cr_recovering: bool,
         right_cr_missing: bool,
@@ -416,6 +517,7 @@ impl Sample {
                 right_cr_recovering,
             ),
             resolution: res_x.and_then(|x| res_y.map(|y| [x, y])),
+            interpolated,
         }
     }
 }
@@ -435,6 +537,8 @@ impl From<Vec<Element>> for Experiment {
         let mut trials: Vec<Trial> = Vec::new();
         let mut variable_labels = None;
         let mut meta = MetaData::default();
+        // Between TRIALID and TRIAL_RESULT
+        let mut in_trial = false;
 
         for el in value {
             match el {
@@ -463,13 +567,50 @@ impl From<Vec<Element>> for Experiment {
                         .expect("Raw sample outside trial")
                         .raw_samples
                         .push(RawSample::from_asc(time, left, right)),
-                    MsgType::TrialId(id) => trials.push(Trial::from_trial_start(id, time)),
+                    MsgType::TrialId(id) => {
+                        trials.push(Trial::from_trial_start(id, time));
+                        in_trial = true;
+                    }
                     MsgType::TrialResult(_) => {
                         trials
                             .last_mut()
                             .expect("Invalid end of trial")
                             .time_record
-                            .end = time
+                            .end = time;
+                        in_trial = false;
+                    }
+                    MsgType::RecordingConfiguration {
+                        tracking_mode,
+                        sampling_rate,
+                        eyes,
+                        ..
+                    } if meta.sampling_rate.is_none() => {
+                        meta.sampling_rate = Some(sampling_rate);
+                        meta.recorded_eyes = eyes.into();
+                        meta.tracking_mode = Some(
+                            match tracking_mode {
+                                TrackingMode::Pupil => "P",
+                                TrackingMode::CR => "CR",
+                            }
+                            .to_string(),
+                        );
+                    }
+                    MsgType::TrackingAlgorithm(algorithm) => {
+                        meta.pupil_fit_method.get_or_insert_with(|| {
+                            match algorithm {
+                                TrackingAlgorithm::Ellipse => "ellipse",
+                                TrackingAlgorithm::Centroid => "centroid",
+                            }
+                            .to_string()
+                        });
+                    }
+                    MsgType::GazeCoordinates {
+                        left,
+                        top,
+                        right,
+                        bottom,
+                    } => {
+                        meta.gaze_coords.get_or_insert([left, top, right, bottom]);
                     }
                     MsgType::TrialVarLabels(labels) => {
                         variable_labels = Some(labels);
@@ -501,6 +642,21 @@ impl From<Vec<Element>> for Experiment {
                         }
                         _ => {}
                     },
+                    MsgType::Other(text) => {
+                        let text = text.trim();
+                        if let Some(cal) = text.strip_prefix("!CAL CALIBRATION ") {
+                            if meta.calibration_type.is_none() {
+                                meta.calibration_type =
+                                    cal.split_whitespace().next().map(|s| s.to_string());
+                            }
+                        }
+                        if let Some(trial) = trials.last_mut().filter(|_| in_trial) {
+                            trial.messages.push(Message {
+                                time,
+                                text: text.to_string(),
+                            });
+                        }
+                    }
                     _ => {}
                 },
                 Element::Preamble(p) => match p {
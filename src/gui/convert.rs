@@ -1,14 +1,10 @@
 use crate::gui::TabView;
-use ascc::load_asc_from_file;
+use ascc::generic::Experiment;
+use ascc::io::Format;
 use atomic_float::{AtomicF32, AtomicF64};
 use egui::{Color32, Context, Frame, ProgressBar, RichText, Ui, Widget};
 use egui_file::FileDialog;
-use flate2::write::{GzEncoder, ZlibEncoder};
-use flate2::Compression;
-use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Write};
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
 use std::sync::atomic::Ordering;
 use std::sync::mpsc::{channel, Receiver};
 use std::sync::Arc;
@@ -30,6 +26,8 @@ pub struct EdfConverter {
 #[derive(Default, Clone)]
 pub struct ConversionOptions {
     compressed: bool,
+    chunked: bool,
+    matlab: bool,
 }
 
 impl EdfConverter {
@@ -46,75 +44,51 @@ impl EdfConverter {
         }
     }
 
-    fn import(&mut self, ctx: &Context) {
+    fn import(&mut self, _ctx: &Context) {
         let files = self.files.clone();
 
         let (tx, rx) = channel();
         self.msg_channel = Some(rx);
 
-        let ctx_clone = ctx.clone();
-
         let progress = self.progress.clone();
         let options = self.options.clone();
 
         let handle = thread::spawn(move || {
             let step = 1. / (files.len() as f32);
             for file in files {
-                let mut asc_name = file.clone();
-                asc_name.set_extension("asc");
-
-                tx.send(format!("converting {} to asc", file.display()))?;
-                let mut child = Command::new("edf2asc")
-                    .arg(file.clone())
-                    .arg("-y")
-                    .arg("-res")
-                    .arg("-vel")
-                    .arg("-ftime")
-                    .arg("-input")
-                    .stdout(Stdio::piped())
-                    .spawn()
-                    .unwrap();
-
-                let child_stdout = child.stdout.take().expect("no stdout");
-
-                let ctx_clone = ctx_clone.clone();
-
-                // let stdout_thread = thread::spawn(move || {
-                //     let stdout_lines = BufReader::new(child_stdout).lines();
-                //     for line in stdout_lines {
-                //         let line = line.unwrap();
-                //         println!("{}", line);
-                //         ctx_clone.request_repaint();
-                //         tx.send(format!("{line}\n")).unwrap();
-                //     }
-                // });
-
-                let _ = child.wait()?;
-                progress.fetch_add(step / 3., Ordering::Relaxed);
-
-                tx.send(format!("importing {}", asc_name.display()))?;
-                let exp = load_asc_from_file(asc_name)?;
-                progress.fetch_add(step / 3., Ordering::Relaxed);
+                tx.send(format!("importing {}", file.display()))?;
+                let exp = Experiment::load(&file)?;
+                progress.fetch_add(2. * step / 3., Ordering::Relaxed);
 
                 // ciborium::ser::into_writer(&exp, BufWriter::new(out_file))?;
                 // serde_json::to_writer(BufWriter::new(out_file), &exp)?;
                 // let v = postcard::to_stdvec(&exp)?;
                 // BufWriter::new(out_file).write(&v)?;
-                let bytes = rkyv::to_bytes::<_, 256>(&exp)?;
                 let mut exported_file = file.clone();
 
-                if options.compressed {
-                    exported_file.set_extension("dat.archive");
-                    let out_file = File::create(&exported_file)?;
+                if options.matlab {
+                    exported_file.set_extension("mat");
                     tx.send(format!("writing to {}", exported_file.display()))?;
-                    let mut e = GzEncoder::new(Vec::new(), Compression::fast());
-                    e.write_all(&bytes).unwrap();
-                    BufWriter::new(out_file).write(&e.finish().unwrap())?;
+                    exp.write_mat(&exported_file, options.compressed)?;
+                    progress.fetch_add(step / 3., Ordering::Relaxed);
+                    continue;
+                }
+
+                let format = if options.chunked {
+                    exported_file.set_extension("dat.chunked");
+                    Format::Chunked
+                } else if options.compressed {
+                    exported_file.set_extension("dat.archive");
+                    Format::Rkyv
                 } else {
                     exported_file.set_extension("dat");
-                    let out_file = File::create(&exported_file)?;
-                    tx.send(format!("writing to {}", exported_file.display()))?;
-                    BufWriter::new(out_file).write(&bytes)?;
+                    Format::Rkyv
+                };
+                tx.send(format!("writing to {}", exported_file.display()))?;
+                if options.compressed {
+                    exp.save_compressed(&exported_file, format)?;
+                } else {
+                    exp.save(&exported_file, format)?;
                 }
 
                 progress.fetch_add(step / 3., Ordering::Relaxed);
@@ -132,6 +106,8 @@ impl TabView for EdfConverter {
         ui.heading("Converter");
 
         ui.checkbox(&mut self.options.compressed, "Compress output");
+        ui.checkbox(&mut self.options.chunked, "Store trials separately");
+        ui.checkbox(&mut self.options.matlab, "MATLAB (.mat) output");
 
         Frame::none().fill(Color32::DARK_GRAY).show(ui, |ui| {
             for f in &self.files {
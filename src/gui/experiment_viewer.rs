@@ -1,13 +1,31 @@
 use crate::gui::plots::create_line;
 use crate::gui::TabView;
-use ascc::generic::Experiment;
+use ascc::chunked::ChunkedReader;
+use ascc::generic::{Experiment, MetaData, Trial};
 use egui::{plot, Color32, RichText, Ui};
 use egui_extras::{Column, TableBuilder};
+use std::fs::File;
+use std::io::BufReader;
 use std::iter::zip;
 
+/// Trials of the viewed experiment.
+enum Trials {
+    /// The whole experiment is in memory
+    Loaded(Vec<Trial>),
+    /// Trials of a chunked container are read when selected, keeping the last one
+    Chunked {
+        reader: ChunkedReader<BufReader<File>>,
+        current: Option<(usize, Trial)>,
+    },
+}
+
 pub struct ExperimentViewer {
     pub id: String,
-    pub exp: Experiment,
+    pub meta: MetaData,
+    pub variable_labels: Vec<String>,
+    /// Id and variables of each trial, available without loading the trials
+    pub trial_info: Vec<(u32, Vec<String>)>,
+    trials: Trials,
     pub open_trials: Vec<bool>,
     pub current_trial: i32,
     pub show_metadata: bool,
@@ -27,10 +45,53 @@ pub struct PlotOptions {
 
 impl ExperimentViewer {
     pub fn new(exp: Experiment, id: String) -> Self {
-        let open_trials = vec![false; exp.trials.len()];
+        let trial_info = exp
+            .trials
+            .iter()
+            .map(|t| (t.id, t.variables.clone()))
+            .collect();
+        Self::with_trials(
+            id,
+            exp.meta,
+            exp.variable_labels,
+            trial_info,
+            Trials::Loaded(exp.trials),
+        )
+    }
+
+    /// Viewer reading the trials of a chunked container on demand.
+    pub fn from_chunked(reader: ChunkedReader<BufReader<File>>, id: String) -> Self {
+        let trial_info = reader
+            .entries()
+            .iter()
+            .map(|e| (e.id, e.variables.clone()))
+            .collect();
+        Self::with_trials(
+            id,
+            reader.meta().clone(),
+            reader.variable_labels().to_vec(),
+            trial_info,
+            Trials::Chunked {
+                reader,
+                current: None,
+            },
+        )
+    }
+
+    fn with_trials(
+        id: String,
+        meta: MetaData,
+        variable_labels: Vec<String>,
+        trial_info: Vec<(u32, Vec<String>)>,
+        trials: Trials,
+    ) -> Self {
+        let open_trials = vec![false; trial_info.len()];
         ExperimentViewer {
             id,
-            exp,
+            meta,
+            variable_labels,
+            trial_info,
+            trials,
             open_trials,
             current_trial: 0,
             show_metadata: false,
@@ -38,20 +99,38 @@ impl ExperimentViewer {
             plot_options: PlotOptions::default(),
         }
     }
+
+    /// The selected trial, read from the container if it is not loaded yet.
+    fn current(&mut self) -> anyhow::Result<&Trial> {
+        let index = self.current_trial as usize;
+        match &mut self.trials {
+            Trials::Loaded(trials) => trials
+                .get(index)
+                .ok_or_else(|| anyhow::anyhow!("Experiment has no trials")),
+            Trials::Chunked { reader, current } => {
+                if current.as_ref().map(|(i, _)| *i) != Some(index) {
+                    *current = Some((index, reader.load_trial(index)?));
+                }
+                Ok(&current.as_ref().unwrap().1)
+            }
+        }
+    }
 }
 
 impl TabView for ExperimentViewer {
     fn ui(&mut self, ui: &mut Ui) {
-        let current_trial = self.exp.trials[self.current_trial as usize].clone();
+        let current_trial = match self.current() {
+            Ok(trial) => trial.clone(),
+            Err(e) => {
+                ui.label(RichText::new(format!("{e:#}")).color(Color32::RED));
+                return;
+            }
+        };
 
         egui::SidePanel::left(format!("left_panel_{}", &self.id)).show_inside(ui, |ui| {
             ui.vertical_centered(|ui| ui.heading("Trials:"));
-            for (i, trial) in self.exp.trials.iter().enumerate() {
-                ui.selectable_value(
-                    &mut self.current_trial,
-                    i as i32,
-                    format!("trial {}", trial.id),
-                );
+            for (i, (id, _)) in self.trial_info.iter().enumerate() {
+                ui.selectable_value(&mut self.current_trial, i as i32, format!("trial {id}"));
             }
 
             ui.separator();
@@ -140,7 +219,7 @@ impl TabView for ExperimentViewer {
 
         if self.show_metadata {
             egui::Window::new("Metadata").show(ui.ctx(), |ui| {
-                for l in &self.exp.meta.preamble_lines {
+                for l in &self.meta.preamble_lines {
                     ui.label(l);
                 }
             });
@@ -153,29 +232,26 @@ impl TabView for ExperimentViewer {
             .show(ui.ctx(), |ui| {
                 TableBuilder::new(ui)
                     .column(Column::exact(50.))
-                    .columns(
-                        Column::auto().at_least(200.),
-                        self.exp.variable_labels.len(),
-                    )
+                    .columns(Column::auto().at_least(200.), self.variable_labels.len())
                     .header(20.0, |mut header| {
                         header.col(|ui| {
                             ui.label("Variable");
                         });
-                        for name in &self.exp.variable_labels {
+                        for name in &self.variable_labels {
                             header.col(|ui| {
                                 ui.label(name);
                             });
                         }
                     })
                     .body(|mut body| {
-                        for (i, trial) in self.exp.trials.iter().enumerate() {
+                        for (i, (_, variables)) in self.trial_info.iter().enumerate() {
                             body.row(20.0, |mut row| {
                                 row.col(|ui| {
                                     ui.label(format!("trial {i}"));
                                 });
-                                for i in 0..self.exp.variable_labels.len() {
+                                for i in 0..self.variable_labels.len() {
                                     row.col(|ui| {
-                                        ui.label(&trial.variables[i]);
+                                        ui.label(variables.get(i).map_or("", |v| v.as_str()));
                                     });
                                 }
                             });
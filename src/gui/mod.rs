@@ -1,16 +1,16 @@
 use std::fs::File;
-use std::io::{BufReader, Read, Write};
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 use eframe::Frame;
 use egui::{Color32, Context, RichText, Ui, Vec2, WidgetText};
 use egui_dock::{DockArea, NodeIndex, Style, TabViewer, Tree};
 use egui_file::FileDialog;
-use flate2::write::{GzDecoder, ZlibDecoder};
 
 use crate::gui::convert::EdfConverter;
+use ascc::chunked::ChunkedReader;
 use ascc::generic::{Experiment, Trial};
-use ascc::load_asc_from_file;
+use ascc::io::Format;
 
 use crate::gui::experiment_viewer::ExperimentViewer;
 use crate::gui::home::HomeView;
@@ -78,79 +78,18 @@ impl eframe::App for TrackerToolsApp {
         if let Some(dialog) = &mut self.file_dialog {
             if dialog.show(ctx).selected() {
                 if let Some(file) = dialog.path() {
-                    let ext = file.extension().unwrap().to_str().unwrap();
                     let title = file.file_name().unwrap().to_str().unwrap();
-                    match ext {
-                        "asc" => {
-                            self.opened_file = Some(file.clone());
-
-                            let exp = load_asc_from_file(file.clone()).unwrap();
-
-                            self.tabs.push_to_first_leaf(Tab::new(
-                                title,
-                                ExperimentViewer::new(exp.clone(), title.to_string()),
-                            ));
-                        }
-                        "EDF" | "edf" => {
-                            self.tabs.push_to_first_leaf(Tab::new(
-                                title,
-                                EdfConverter::new(vec![file.clone()]),
-                            ));
-                        }
-                        "cbor" => {
-                            let base = File::open(&file).unwrap();
-
-                            let exp = ciborium::de::from_reader(BufReader::new(base)).unwrap();
-                            self.tabs.push_to_first_leaf(Tab::new(
-                                title,
-                                ExperimentViewer::new(exp, title.to_string()),
-                            ));
-                        }
-                        "json" => {
-                            let base = File::open(&file).unwrap();
-
-                            let exp = serde_json::from_reader(BufReader::new(base)).unwrap();
-                            self.tabs.push_to_first_leaf(Tab::new(
-                                title,
-                                ExperimentViewer::new(exp, title.to_string()),
-                            ));
-                        }
-                        "pc" => {
-                            let base = File::open(&file).unwrap();
-
-                            let mut bytes = Vec::new();
-                            BufReader::new(base).read_to_end(&mut bytes).unwrap();
-                            let exp = postcard::from_bytes(bytes.as_slice()).unwrap();
-                            self.tabs.push_to_first_leaf(Tab::new(
-                                title,
-                                ExperimentViewer::new(exp, title.to_string()),
-                            ));
+                    if Format::from_path(&file) == Some(Format::Edf) {
+                        self.tabs.push_to_first_leaf(Tab::new(
+                            title,
+                            EdfConverter::new(vec![file.clone()]),
+                        ));
+                    } else {
+                        self.opened_file = Some(file.clone());
+                        match open_experiment(&file, title) {
+                            Ok(viewer) => self.tabs.push_to_first_leaf(Tab::new(title, viewer)),
+                            Err(e) => self.status = AppStatus::Err(format!("{e:#}")),
                         }
-                        "dat" => {
-                            let base = File::open(&file).unwrap();
-
-                            let mut bytes = Vec::new();
-                            BufReader::new(base).read_to_end(&mut bytes).unwrap();
-                            let exp = rkyv::from_bytes(&bytes).unwrap();
-                            self.tabs.push_to_first_leaf(Tab::new(
-                                title,
-                                ExperimentViewer::new(exp, title.to_string()),
-                            ));
-                        }
-                        "archive" => {
-                            let base = File::open(&file).unwrap();
-
-                            let mut bytes = Vec::new();
-                            BufReader::new(base).read_to_end(&mut bytes).unwrap();
-                            let mut e = GzDecoder::new(Vec::new());
-                            e.write_all(&mut bytes).unwrap();
-                            let exp = rkyv::from_bytes(e.finish().unwrap().as_slice()).unwrap();
-                            self.tabs.push_to_first_leaf(Tab::new(
-                                title,
-                                ExperimentViewer::new(exp, title.to_string()),
-                            ));
-                        }
-                        _ => self.status = AppStatus::Err(format!("invalid file extension {ext}")),
                     }
                 }
             }
@@ -174,6 +113,19 @@ impl eframe::App for TrackerToolsApp {
     }
 }
 
+/// Opens chunked containers for reading trials on demand and loads any other file as a whole.
+fn open_experiment(file: &Path, title: &str) -> anyhow::Result<ExperimentViewer> {
+    let mut start = Vec::new();
+    File::open(file)?.take(4096).read_to_end(&mut start)?;
+    if Format::detect(&start) == Some(Format::Chunked) {
+        let reader = ChunkedReader::open(file)?;
+        Ok(ExperimentViewer::from_chunked(reader, title.to_string()))
+    } else {
+        let exp = Experiment::load(file)?;
+        Ok(ExperimentViewer::new(exp, title.to_string()))
+    }
+}
+
 pub struct TrialWindow {
     pub trial: Trial,
 }
@@ -0,0 +1,571 @@
+//! Loading and saving experiments in all supported formats.
+//!
+//! [`Experiment::load`] detects the format from the content of the file, falling back to the
+//! extension for formats without a signature (postcard and containers written before the
+//! versioned header). Any of the formats may be gzipped.
+
+use crate::container::{self, Header, GZIP_MAGIC};
+use crate::generic::Experiment;
+use crate::{asc_to_generic, chunked, load_asc_from_file};
+use anyhow::{anyhow, bail, Context};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// EyeLink ASC text, load only
+    Asc,
+    /// EyeLink EDF, converted with `edf2asc` when loading, load only
+    Edf,
+    Json,
+    Cbor,
+    Postcard,
+    /// Versioned rkyv container, see [`crate::container`]
+    Rkyv,
+    /// Chunked rkyv container, see [`crate::chunked`]
+    Chunked,
+}
+
+/// Start of EDF files written by EyeLink hosts
+const EDF_MAGIC: &[u8] = b"SR_RESEARCH";
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Asc => "asc",
+            Format::Edf => "edf",
+            Format::Json => "json",
+            Format::Cbor => "cbor",
+            Format::Postcard => "pc",
+            Format::Rkyv => "dat",
+            Format::Chunked => "dat.chunked",
+        }
+    }
+
+    /// Infers the format from the extension of `path`, ignoring a trailing `.gz`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let mut path = path.to_path_buf();
+        let mut ext = path.extension()?.to_str()?.to_lowercase();
+        if ext == "gz" {
+            path.set_extension("");
+            ext = path.extension()?.to_str()?.to_lowercase();
+        }
+        ext.parse().ok()
+    }
+
+    /// Detects the format from the start of the (decompressed) content.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if let Ok(Some((header, _))) = Header::parse(bytes) {
+            return Some(if header.chunked {
+                Format::Chunked
+            } else {
+                Format::Rkyv
+            });
+        }
+        if bytes.starts_with(EDF_MAGIC) {
+            return Some(Format::Edf);
+        }
+        let start = bytes.iter().position(|b| !b.is_ascii_whitespace());
+        let text = &bytes[start.unwrap_or(bytes.len())..];
+        if text.starts_with(b"**") || text.starts_with(b"MSG") {
+            return Some(Format::Asc);
+        }
+        if text.starts_with(b"{") {
+            return Some(Format::Json);
+        }
+        // An experiment is a map of three fields, of definite or indefinite length
+        match bytes.first() {
+            Some(0xa3 | 0xbf) => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "asc" => Ok(Format::Asc),
+            "edf" => Ok(Format::Edf),
+            "json" => Ok(Format::Json),
+            "cbor" => Ok(Format::Cbor),
+            "pc" | "postcard" => Ok(Format::Postcard),
+            "dat" | "rkyv" | "archive" => Ok(Format::Rkyv),
+            "chunked" => Ok(Format::Chunked),
+            _ => bail!("Unknown experiment format {s}"),
+        }
+    }
+}
+
+/// Formats experiments can be converted to: the serialized formats of [`Format`], MAT-files and,
+/// with the `dataframes` feature, tables in Arrow IPC, Parquet or CSV files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Json,
+    Cbor,
+    Postcard,
+    Rkyv,
+    /// Gzip compressed rkyv
+    RkyvGz,
+    /// Rkyv with each trial stored separately, gzipped with `--compress`
+    Chunked,
+    /// A directory with one Arrow IPC file per table
+    Arrow,
+    /// One Parquet file per table
+    Parquet,
+    /// One CSV file per table
+    Csv,
+    /// MATLAB MAT-file (v5)
+    Mat,
+}
+
+impl OutputFormat {
+    /// The serialized experiment format written, `None` for MAT-files and tables.
+    pub fn experiment_format(&self) -> Option<Format> {
+        match self {
+            OutputFormat::Json => Some(Format::Json),
+            OutputFormat::Cbor => Some(Format::Cbor),
+            OutputFormat::Postcard => Some(Format::Postcard),
+            OutputFormat::Rkyv | OutputFormat::RkyvGz => Some(Format::Rkyv),
+            OutputFormat::Chunked => Some(Format::Chunked),
+            OutputFormat::Arrow | OutputFormat::Parquet | OutputFormat::Csv | OutputFormat::Mat => {
+                None
+            }
+        }
+    }
+
+    /// Extension of the written file, empty for the Arrow directory.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::RkyvGz => "dat.archive",
+            OutputFormat::Arrow => "",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Mat => "mat",
+            _ => self.experiment_format().unwrap().extension(),
+        }
+    }
+
+    /// Infers the format from the extension of `path`. Gzipped serde formats keep their format,
+    /// other gzipped files are taken to be rkyv.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "archive" => Some(OutputFormat::RkyvGz),
+            "gz" => match Format::from_path(path) {
+                Some(Format::Json) => Some(OutputFormat::Json),
+                Some(Format::Cbor) => Some(OutputFormat::Cbor),
+                Some(Format::Postcard) => Some(OutputFormat::Postcard),
+                _ => Some(OutputFormat::RkyvGz),
+            },
+            "parquet" => Some(OutputFormat::Parquet),
+            "csv" => Some(OutputFormat::Csv),
+            "mat" => Some(OutputFormat::Mat),
+            _ => match ext.parse().ok()? {
+                Format::Asc | Format::Edf => None,
+                Format::Json => Some(OutputFormat::Json),
+                Format::Cbor => Some(OutputFormat::Cbor),
+                Format::Postcard => Some(OutputFormat::Postcard),
+                Format::Rkyv => Some(OutputFormat::Rkyv),
+                Format::Chunked => Some(OutputFormat::Chunked),
+            },
+        }
+    }
+
+    /// Name of the output for `input` in a batch conversion, a directory for Arrow tables.
+    pub fn file_name(&self, input: &Path, compress: bool) -> String {
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        match self {
+            OutputFormat::Arrow => stem.to_string(),
+            OutputFormat::Json | OutputFormat::Cbor | OutputFormat::Postcard if compress => {
+                format!("{stem}.{}.gz", self.extension())
+            }
+            _ => format!("{stem}.{}", self.extension()),
+        }
+    }
+
+    /// Targets of a batch conversion of `inputs` into `output_dir`, in the order of `inputs`.
+    /// Fails listing the inputs that would be written to the same target, e.g. files with the
+    /// same name from different directories.
+    pub fn batch_targets(
+        &self,
+        inputs: &[PathBuf],
+        output_dir: &Path,
+        compress: bool,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let targets: Vec<PathBuf> = inputs
+            .iter()
+            .map(|input| output_dir.join(self.file_name(input, compress)))
+            .collect();
+        let mut by_target: HashMap<&Path, Vec<&Path>> = HashMap::new();
+        for (input, target) in inputs.iter().zip(&targets) {
+            by_target.entry(target).or_default().push(input);
+        }
+        let mut clashes: Vec<String> = by_target
+            .into_iter()
+            .filter(|(_, inputs)| inputs.len() > 1)
+            .map(|(target, inputs)| {
+                let inputs: Vec<String> = inputs.iter().map(|p| p.display().to_string()).collect();
+                format!("{}: {}", target.display(), inputs.join(", "))
+            })
+            .collect();
+        if !clashes.is_empty() {
+            clashes.sort();
+            bail!(
+                "Several inputs would be written to the same file:\n{}",
+                clashes.join("\n")
+            );
+        }
+        Ok(targets)
+    }
+
+    /// Saves `exp` to `path`. `compress` gzips serialized experiments, compresses MAT-files with
+    /// zlib and is ignored for tables.
+    pub fn save(&self, exp: &Experiment, path: &Path, compress: bool) -> anyhow::Result<()> {
+        if let Some(format) = self.experiment_format() {
+            let compress = compress || *self == OutputFormat::RkyvGz;
+            return exp.write(BufWriter::new(File::create(path)?), format, compress);
+        }
+        match self {
+            OutputFormat::Mat => exp.write_mat(path, compress),
+            _ => self.save_tables(exp, path),
+        }
+    }
+
+    #[cfg(feature = "dataframes")]
+    fn save_tables(&self, exp: &Experiment, path: &Path) -> anyhow::Result<()> {
+        match self {
+            OutputFormat::Arrow => exp.write_arrow(path, crate::ipc::DecimalColumns::default()),
+            _ => exp.write_tables(path, *self),
+        }
+        .map(|_| ())
+    }
+
+    #[cfg(not(feature = "dataframes"))]
+    fn save_tables(&self, _exp: &Experiment, _path: &Path) -> anyhow::Result<()> {
+        bail!("{self:?} output requires the `dataframes` feature")
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(OutputFormat::Json),
+            "cbor" => Ok(OutputFormat::Cbor),
+            "pc" | "postcard" => Ok(OutputFormat::Postcard),
+            "dat" | "rkyv" => Ok(OutputFormat::Rkyv),
+            "rkyv-gz" | "archive" => Ok(OutputFormat::RkyvGz),
+            "chunked" => Ok(OutputFormat::Chunked),
+            "arrow" => Ok(OutputFormat::Arrow),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "csv" => Ok(OutputFormat::Csv),
+            "mat" => Ok(OutputFormat::Mat),
+            _ => bail!("Unknown output format {s}"),
+        }
+    }
+}
+
+/// Picks the format of `content` given the format expected from the file extension.
+///
+/// Postcard and headerless rkyv archives have no signature, and their content can look like one
+/// of the other formats, so the extension takes precedence for them.
+fn resolve(content: &[u8], hint: Option<Format>) -> Option<Format> {
+    match (Format::detect(content), hint) {
+        (Some(detected @ (Format::Rkyv | Format::Chunked)), _) => Some(detected),
+        (_, Some(Format::Postcard | Format::Rkyv)) => hint,
+        (detected, _) => detected.or(hint),
+    }
+}
+
+/// Decodes a postcard experiment. Postcard is not self-describing, so fields added since the
+/// first release cannot be defaulted; files without them are decoded with the layout of that
+/// release, which is the one of container schema version 1.
+fn from_postcard(bytes: &[u8]) -> anyhow::Result<Experiment> {
+    let current = postcard::take_from_bytes::<Experiment>(bytes);
+    if let Ok((exp, [])) = current {
+        return Ok(exp);
+    }
+    if let Ok((exp, [])) = postcard::take_from_bytes::<container::v1::Experiment>(bytes) {
+        return Ok(exp.into());
+    }
+    match current {
+        Ok(_) => bail!("Trailing bytes after the postcard experiment"),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Converts an EDF file with `edf2asc` into a temporary directory and parses the result. Nothing
+/// is written next to the EDF file.
+pub fn load_edf(path: &Path) -> anyhow::Result<Experiment> {
+    static CONVERSIONS: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "ascc-edf2asc-{}-{}",
+        std::process::id(),
+        CONVERSIONS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).with_context(|| format!("Could not create {}", dir.display()))?;
+    let result = convert_edf(path, &dir).and_then(load_asc_from_file);
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+/// Runs `edf2asc` on `path`, writing the ASC file to `dir`.
+fn convert_edf(path: &Path, dir: &Path) -> anyhow::Result<PathBuf> {
+    let status = Command::new("edf2asc")
+        .arg(path)
+        .arg("-p")
+        .arg(dir)
+        .arg("-res")
+        .arg("-vel")
+        .arg("-ftime")
+        .arg("-input")
+        .stdout(Stdio::null())
+        .status()
+        .context("failed to run edf2asc")?;
+    if !status.success() {
+        bail!("edf2asc exited with {status}");
+    }
+    let name = path.file_name().context("EDF path has no file name")?;
+    Ok(dir.join(name).with_extension("asc"))
+}
+
+impl Experiment {
+    /// Loads an experiment in any of the supported formats.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Experiment> {
+        let path = path.as_ref();
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Could not open {}", path.display()))?;
+        let hint = Format::from_path(path);
+        if resolve(&bytes, hint) == Some(Format::Edf) {
+            return load_edf(path);
+        }
+        Self::from_bytes(&bytes, hint).with_context(|| format!("Could not load {}", path.display()))
+    }
+
+    /// Parses an experiment from `bytes`. `hint` is used if the format cannot be detected.
+    pub fn from_bytes(bytes: &[u8], hint: Option<Format>) -> anyhow::Result<Experiment> {
+        let mut inner = Vec::new();
+        let content = if bytes.starts_with(&GZIP_MAGIC) {
+            GzDecoder::new(bytes)
+                .read_to_end(&mut inner)
+                .context("Could not decompress file")?;
+            inner.as_slice()
+        } else {
+            bytes
+        };
+
+        match resolve(content, hint) {
+            Some(Format::Asc) => asc_to_generic(std::str::from_utf8(content)?),
+            Some(Format::Edf) => bail!("EDF files can only be loaded from a path"),
+            Some(Format::Json) => Ok(serde_json::from_slice(content)?),
+            Some(Format::Cbor) => Ok(ciborium::de::from_reader(content)?),
+            Some(Format::Postcard) => from_postcard(content),
+            // Containers have their own compression flag, headerless archives are from before
+            // the header existed
+            Some(Format::Rkyv | Format::Chunked) | None => container::from_bytes(content),
+        }
+    }
+
+    /// Writes the experiment in `format`, gzipped if `compressed` is set. Containers record the
+    /// compression in their header rather than being wrapped in gzip.
+    pub fn write<W: Write>(
+        &self,
+        mut writer: W,
+        format: Format,
+        compressed: bool,
+    ) -> anyhow::Result<()> {
+        match format {
+            Format::Asc | Format::Edf => bail!("Experiments cannot be saved as {format:?}"),
+            Format::Rkyv => return container::write_experiment(writer, self, compressed),
+            Format::Chunked => return chunked::write_chunked(writer, self, compressed),
+            _ => {}
+        }
+        if compressed {
+            let mut e = GzEncoder::new(writer, Compression::fast());
+            self.write_serde(&mut e, format)?;
+            e.finish()?.flush()?;
+        } else {
+            self.write_serde(&mut writer, format)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write_serde<W: Write>(&self, writer: &mut W, format: Format) -> anyhow::Result<()> {
+        match format {
+            Format::Json => serde_json::to_writer(writer, self)?,
+            Format::Cbor => ciborium::ser::into_writer(self, writer)?,
+            Format::Postcard => writer.write_all(&postcard::to_stdvec(self)?)?,
+            _ => return Err(anyhow!("{format:?} is not a serde format")),
+        }
+        Ok(())
+    }
+
+    /// Saves the experiment to `path` in `format`.
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: Format) -> anyhow::Result<()> {
+        self.write(BufWriter::new(File::create(path)?), format, false)
+    }
+
+    /// Saves the experiment to `path` in `format`, compressed.
+    pub fn save_compressed<P: AsRef<Path>>(&self, path: P, format: Format) -> anyhow::Result<()> {
+        self.write(BufWriter::new(File::create(path)?), format, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generic::MetaData;
+
+    #[test]
+    fn test_detect_roundtrip() {
+        let exp = Experiment {
+            meta: MetaData::default(),
+            variable_labels: vec!["condition".to_string()],
+            trials: Vec::new(),
+        };
+        for format in [
+            Format::Json,
+            Format::Cbor,
+            Format::Postcard,
+            Format::Rkyv,
+            Format::Chunked,
+        ] {
+            for compressed in [false, true] {
+                let mut bytes = Vec::new();
+                exp.write(&mut bytes, format, compressed).unwrap();
+                let hint = (format == Format::Postcard).then_some(format);
+                let read = Experiment::from_bytes(&bytes, hint).unwrap();
+                assert_eq!(read.variable_labels, ["condition"], "{format:?}");
+            }
+        }
+        assert_eq!(
+            Format::from_path(Path::new("a/b.json.gz")),
+            Some(Format::Json)
+        );
+        assert_eq!(Format::detect(b"** CONVERTED FROM"), Some(Format::Asc));
+    }
+
+    #[test]
+    fn test_load_postcard_without_new_fields() {
+        use crate::container::v1;
+        use crate::generic::TimeRecord;
+        use crate::Decimal;
+
+        let time = Decimal::from(1000);
+        let exp = v1::Experiment {
+            meta: v1::MetaData {
+                recording_datetime: Default::default(),
+                preamble_lines: vec!["** DATE: Wed Jun 14 10:00:00 2023".to_string()],
+            },
+            variable_labels: vec!["condition".to_string()],
+            trials: vec![v1::Trial {
+                id: 1,
+                time_record: TimeRecord {
+                    start: time,
+                    end: time,
+                },
+                samples: vec![v1::Sample {
+                    time,
+                    left: None,
+                    right: None,
+                    resolution: None,
+                }],
+                raw_samples: Vec::new(),
+                events: Vec::new(),
+                camera_frames: Vec::new(),
+                variables: vec!["a".to_string()],
+                targets: Default::default(),
+            }],
+        };
+        let bytes = postcard::to_stdvec(&exp).unwrap();
+        let read = Experiment::from_bytes(&bytes, Some(Format::Postcard)).unwrap();
+        assert_eq!(read.variable_labels, ["condition"]);
+        assert_eq!(read.trials[0].variables, ["a"]);
+        assert_eq!(read.trials[0].samples.len(), 1);
+        assert!(!read.trials[0].samples[0].interpolated);
+        assert!(read.meta.sampling_rate.is_none());
+
+        let bytes = postcard::to_stdvec(&read).unwrap();
+        let read = Experiment::from_bytes(&bytes, Some(Format::Postcard)).unwrap();
+        assert_eq!(read.trials[0].samples[0].time, time);
+    }
+
+    #[test]
+    fn test_output_format() {
+        for (path, format) in [
+            ("a.json.gz", OutputFormat::Json),
+            ("a.dat", OutputFormat::Rkyv),
+            ("a.dat.gz", OutputFormat::RkyvGz),
+            ("a.dat.chunked", OutputFormat::Chunked),
+            ("a.mat", OutputFormat::Mat),
+            ("a.csv", OutputFormat::Csv),
+        ] {
+            assert_eq!(OutputFormat::from_path(Path::new(path)), Some(format));
+        }
+        assert_eq!(OutputFormat::from_path(Path::new("a.asc")), None);
+        assert_eq!(
+            "rkyv-gz".parse::<OutputFormat>().unwrap(),
+            OutputFormat::RkyvGz
+        );
+
+        let input = Path::new("data/p01.asc");
+        assert_eq!(OutputFormat::Cbor.file_name(input, true), "p01.cbor.gz");
+        assert_eq!(OutputFormat::Rkyv.file_name(input, true), "p01.dat");
+        assert_eq!(OutputFormat::Arrow.file_name(input, false), "p01");
+
+        let inputs = [
+            PathBuf::from("sub-01/p01.asc"),
+            PathBuf::from("sub-02/p01.edf"),
+            PathBuf::from("sub-02/p02.asc"),
+        ];
+        let error = OutputFormat::Json
+            .batch_targets(&inputs, Path::new("out"), false)
+            .unwrap_err();
+        assert!(error.to_string().contains("sub-01/p01.asc, sub-02/p01.edf"));
+        let targets = OutputFormat::Json
+            .batch_targets(&inputs[1..], Path::new("out"), false)
+            .unwrap();
+        assert_eq!(targets[1], Path::new("out/p02.json"));
+    }
+
+    #[test]
+    fn test_load_json_without_new_fields() {
+        // Written before the recording configuration, interpolation flags and messages were added
+        let json = br#"{
+            "meta": {"recording_datetime": "2023-06-14T10:00:00", "preamble_lines": []},
+            "variable_labels": [],
+            "trials": [{
+                "id": 1,
+                "time_record": {"start": 1000.0, "end": 1002.0},
+                "samples": [{
+                    "time": 1000.0,
+                    "left": {"position": [512.0, 384.0], "area": 1200.0, "velocity": null, "cr": "Found"},
+                    "right": null,
+                    "resolution": null
+                }],
+                "raw_samples": [],
+                "events": [],
+                "camera_frames": [],
+                "variables": [],
+                "targets": {}
+            }]
+        }"#;
+        let exp = Experiment::from_bytes(json, None).unwrap();
+        assert!(exp.meta.sampling_rate.is_none());
+        assert!(exp.meta.recorded_eyes.is_empty());
+        assert!(!exp.trials[0].samples[0].interpolated);
+        assert!(exp.trials[0].messages.is_empty());
+    }
+}
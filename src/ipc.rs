@@ -0,0 +1,169 @@
+//! Arrow IPC (Feather v2) export and zero-copy handoff through the Arrow C Data Interface.
+//!
+//! Four tables are exported, built from the long-format frames of [`crate::export`]:
+//!
+//! * `trials`: `trial_id` (u32) followed by one utf8 column per trial variable.
+//! * `samples`: `trial_id`, `time`, the per eye `*_pos_x/y`, `*_area`, `*_vel_x/y`, `*_cr`
+//!   (utf8) columns, `res_x/y`, `interpolated` (bool), `trial_time` and the trial variables.
+//! * `events`: `trial_id`, `eye`, `kind` (utf8), `start`, `end`, `duration`, `res_x/y`, the
+//!   fixation `average_*` and saccade `start_pos_*`/`end_pos_*` columns, `amplitude` and
+//!   `angle` (f64), `tracker_amplitude`, `peak_velocity`, `trial_time` and the trial variables.
+//! * `raw_samples`: `trial_id`, `time`, the per eye `pupil_*` and `cr_*` columns, `trial_time`
+//!   and the trial variables.
+//!
+//! Times are in ms and positions in screen coordinates. Columns holding values parsed from
+//! the ASC file are written as 128 bit decimals or as f64, chosen with [`DecimalColumns`].
+
+use crate::generic::Experiment;
+use arrow2::array::{Array, StructArray};
+use arrow2::chunk::Chunk;
+use arrow2::datatypes::{DataType as ArrowDataType, Field, Schema};
+use arrow2::ffi;
+use arrow2::io::ipc::write::{FileWriter, WriteOptions};
+use polars::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Representation of the decimal columns in the exported tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecimalColumns {
+    /// Native Arrow `Decimal128`, exact
+    #[default]
+    Decimal,
+    /// `Float64`, supported by every Arrow consumer
+    Float,
+}
+
+pub struct ArrowTable {
+    pub name: &'static str,
+    pub schema: Schema,
+    pub chunks: Vec<Chunk<Box<dyn Array>>>,
+}
+
+impl ArrowTable {
+    pub fn from_frame(
+        name: &'static str,
+        df: DataFrame,
+        decimals: DecimalColumns,
+    ) -> PolarsResult<Self> {
+        let columns = df
+            .get_columns()
+            .iter()
+            .map(|s| match (s.dtype(), decimals) {
+                (DataType::Decimal(_, _), DecimalColumns::Float) => s.cast(&DataType::Float64),
+                _ => Ok(s.rechunk()),
+            })
+            .collect::<PolarsResult<Vec<_>>>()?;
+        let df = DataFrame::new(columns)?;
+
+        Ok(ArrowTable {
+            name,
+            schema: df.schema().to_arrow(),
+            chunks: df.iter_chunks().collect(),
+        })
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.chunks.iter().map(|c| c.len()).sum()
+    }
+
+    /// Writes the table in the Arrow IPC file format (Feather v2).
+    pub fn write_ipc<W: Write>(&self, writer: W) -> anyhow::Result<()> {
+        let mut writer = FileWriter::try_new(
+            writer,
+            self.schema.clone(),
+            None,
+            WriteOptions { compression: None },
+        )?;
+        for chunk in &self.chunks {
+            writer.write(chunk, None)?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Exports the record batches as an Arrow C stream of struct arrays, one per batch.
+    /// The buffers are shared with the consumer, not copied.
+    ///
+    /// From Python the stream can be imported with
+    /// `pyarrow.RecordBatchReader._import_from_c(address)`.
+    pub fn export_to_c(&self) -> ffi::ArrowArrayStream {
+        let data_type = ArrowDataType::Struct(self.schema.fields.clone());
+        let field = Field::new(self.name, data_type.clone(), false);
+
+        let batches: Vec<Box<dyn Array>> = self
+            .chunks
+            .iter()
+            .map(|chunk| StructArray::new(data_type.clone(), chunk.arrays().to_vec(), None).boxed())
+            .collect();
+        ffi::export_iterator(Box::new(batches.into_iter().map(Ok)), field)
+    }
+}
+
+impl Experiment {
+    /// The `trials`, `samples`, `events` and `raw_samples` tables.
+    pub fn arrow_tables(&self, decimals: DecimalColumns) -> PolarsResult<Vec<ArrowTable>> {
+        Ok(vec![
+            ArrowTable::from_frame("trials", self.trial_variables()?, decimals)?,
+            ArrowTable::from_frame("samples", self.samples()?, decimals)?,
+            ArrowTable::from_frame("events", self.events()?, decimals)?,
+            ArrowTable::from_frame("raw_samples", self.raw_samples()?, decimals)?,
+        ])
+    }
+
+    /// Writes every table to `<dir>/<name>.arrow`, returning the written paths.
+    pub fn write_arrow<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        decimals: DecimalColumns,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        let mut paths = Vec::new();
+        for table in self.arrow_tables(decimals)? {
+            let path = dir.join(format!("{}.arrow", table.name));
+            table.write_ipc(BufWriter::new(File::create(&path)?))?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::decimal_to_arrow_decimal;
+    use crate::Decimal;
+    use arrow2::io::ipc::read::{read_file_metadata, FileReader};
+    use std::io::Cursor;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_ipc_roundtrip() {
+        let time: Vec<Decimal> = ["1.5", "2.0", "2.5"]
+            .iter()
+            .map(|s| Decimal::from_str(s).unwrap())
+            .collect();
+        let df = df! [
+            "trial_id" => [1u32, 1, 2],
+            "time" => decimal_to_arrow_decimal(time),
+        ]
+        .unwrap();
+
+        let table = ArrowTable::from_frame("samples", df, DecimalColumns::Float).unwrap();
+        assert_eq!(table.schema.fields[1].data_type, ArrowDataType::Float64);
+
+        let mut bytes = Vec::new();
+        table.write_ipc(&mut bytes).unwrap();
+
+        let mut reader = Cursor::new(bytes);
+        let metadata = read_file_metadata(&mut reader).unwrap();
+        assert_eq!(metadata.schema, table.schema);
+        let chunks: Vec<_> = FileReader::new(reader, metadata, None, None)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(chunks.iter().map(|c| c.len()).sum::<usize>(), 3);
+    }
+}
@@ -1,21 +1,35 @@
 use crate::asc::Element;
 use crate::generic::Experiment;
 use indicatif::{ParallelProgressIterator, ProgressIterator};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
 use anyhow::Context;
+use rust_decimal::prelude::ToPrimitive;
 use std::path::PathBuf;
 use std::str::FromStr;
 
 #[cfg(feature = "py-ext")]
 use serde::{Deserialize, Serialize};
 
+pub mod analysis;
+pub mod anonymize;
 pub mod asc;
+pub mod bids;
+pub mod cache;
+pub mod chunked;
 pub mod common;
+pub mod container;
+pub mod dataset;
 pub mod generic;
+pub mod io;
+pub mod mapped;
+pub mod mat;
+pub mod report;
 
 #[cfg(feature = "dataframes")]
 pub mod export;
+#[cfg(feature = "dataframes")]
+pub mod ipc;
 
 #[cfg(feature = "py-ext")]
 #[derive(
@@ -53,6 +67,16 @@ pub struct NaiveDateTime(chrono::NaiveDateTime);
 #[cfg(not(feature = "py-ext"))]
 pub type NaiveDateTime = chrono::NaiveDateTime;
 
+#[cfg(feature = "py-ext")]
+pub fn decimal_to_f64(input: Decimal) -> f64 {
+    input.0.to_f64().unwrap_or(f64::NAN)
+}
+
+#[cfg(not(feature = "py-ext"))]
+pub fn decimal_to_f64(input: Decimal) -> f64 {
+    input.to_f64().unwrap_or(f64::NAN)
+}
+
 #[cfg(feature = "py-ext")]
 pub mod python;
 
@@ -75,6 +99,30 @@ pub fn asc_to_generic(input: &str) -> anyhow::Result<Experiment> {
     Ok(Experiment::from(res?))
 }
 
+/// Like [`asc_to_generic`], calling `progress` with the number of parsed and total lines after
+/// each percent of the input. Parsing stops at the first error returned by `progress`.
+///
+/// The callback runs on the calling thread, unlike the parsing itself.
+pub fn asc_to_generic_with_callback<F>(input: &str, mut progress: F) -> anyhow::Result<Experiment>
+where
+    F: FnMut(usize, usize) -> anyhow::Result<()>,
+{
+    let lines: Vec<(usize, &str)> = input.lines().enumerate().collect();
+    let total = lines.len();
+    let mut elements = Vec::with_capacity(total);
+    for chunk in lines.chunks(total / 100 + 1) {
+        let res: anyhow::Result<Vec<Element>> = chunk
+            .par_iter()
+            .map(|&(i, e)| {
+                Element::from_str(e).with_context(|| format!("at line {i}, content: {e}"))
+            })
+            .collect();
+        elements.extend(res?);
+        progress(elements.len(), total)?;
+    }
+    Ok(Experiment::from(elements))
+}
+
 pub fn load_asc_from_file_with_progress(path: PathBuf) -> anyhow::Result<Experiment> {
     let s = std::fs::read_to_string(path)?;
     asc_to_generic_with_progress(&s)
@@ -84,3 +132,44 @@ pub fn load_asc_from_file(path: PathBuf) -> anyhow::Result<Experiment> {
     let s = std::fs::read_to_string(path)?;
     asc_to_generic(&s)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording(samples: usize) -> String {
+        let mut asc = "** DATE: Wed Jun 14 10:00:00 2023\nMSG\t1000 TRIALID 1\n".to_string();
+        for t in 1001..1001 + samples {
+            asc.push_str(&format!(
+                "{t}\t100.0\t200.0\t1000.0\t.\t.\t0.0\t1.0\t2.0\t.\t.\t30.0\t31.0\t0.0\t.....\n"
+            ));
+        }
+        asc.push_str(&format!("MSG\t{} TRIAL_RESULT 0\n", 1001 + samples));
+        asc
+    }
+
+    #[test]
+    fn test_asc_to_generic_with_callback() {
+        let asc = recording(250);
+        let mut calls = Vec::new();
+        let exp = asc_to_generic_with_callback(&asc, |done, total| {
+            calls.push((done, total));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(exp.trials[0].samples.len(), 250);
+        // 253 lines in chunks of 3
+        assert_eq!(calls.len(), 85);
+        assert!(calls.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(calls.iter().all(|&(_, total)| total == 253));
+        assert_eq!(calls.last(), Some(&(253, 253)));
+
+        let mut calls = 0;
+        let result = asc_to_generic_with_callback(&asc, |_, _| {
+            calls += 1;
+            anyhow::bail!("cancelled")
+        });
+        assert_eq!(result.unwrap_err().to_string(), "cancelled");
+        assert_eq!(calls, 1);
+    }
+}
@@ -1,19 +1,15 @@
 mod gui;
 
-use anyhow::Result;
-use ascc::asc::Element;
+use anyhow::{anyhow, bail, Result};
+use ascc::anonymize::{AnonymizeOptions, DateHandling, RedactionRule};
+use ascc::cache::ParseCache;
 use ascc::generic::Experiment;
+use ascc::io::OutputFormat;
+use clap::{Parser, Subcommand};
 use indicatif::ParallelProgressIterator;
 use rayon::prelude::*;
 
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufWriter;
-use std::path::PathBuf;
-use std::str::FromStr;
-
-use ascc::{load_asc_from_file, load_asc_from_file_with_progress};
-use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(author, version, about)]
@@ -24,50 +20,233 @@ struct Cli {
 
 #[derive(Subcommand)]
 enum Commands {
+    /// Convert ASC, EDF or serialized experiment files to another format
     Convert {
+        /// Input files
+        #[arg(required = true)]
+        input: Vec<PathBuf>,
+        /// Output file, or output directory when converting several files
         #[arg(short, long)]
+        output: PathBuf,
+        /// Output format, inferred from the output extension if not given
+        #[arg(short, long, value_enum)]
+        format: Option<OutputFormat>,
+        /// Compress the output, gzip for serialized experiments and zlib for MAT-files
+        #[arg(long)]
+        compress: bool,
+        /// Load parsed recordings from the parse cache, adding them if missing
+        #[arg(long)]
+        cache: bool,
+    },
+    /// Inspect or clear the cache of parsed recordings
+    Cache {
+        /// Cache directory, `$ASCC_CACHE_DIR` or the user cache directory by default
+        #[arg(long)]
+        dir: Option<PathBuf>,
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Remove identifying information from an experiment before sharing it
+    Anonymize {
         input: PathBuf,
+        /// Output file, the format is inferred from the extension
         #[arg(short, long)]
         output: PathBuf,
+        /// Remove the recording date
+        #[arg(long, conflicts_with = "shift_days")]
+        strip_dates: bool,
+        /// Shift the recording date by this many days
+        #[arg(long, allow_negative_numbers = true)]
+        shift_days: Option<i64>,
+        /// Keep paths in the preamble instead of reducing them to file names
+        #[arg(long)]
+        keep_paths: bool,
+        /// Regex to redact in messages and trial variables. Can be repeated
+        #[arg(long, value_name = "PATTERN")]
+        redact: Vec<String>,
+        /// Text replacing the matches of `--redact`
+        #[arg(long, default_value = "[REDACTED]")]
+        replacement: String,
+        /// Subtract the time of the first trial or sample from all timestamps
+        #[arg(long)]
+        rebase_time: bool,
+        /// Log of the changes, `<output>.log` by default
+        #[arg(long)]
+        log: Option<PathBuf>,
     },
     Gui,
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// List the cached recordings
+    List,
+    /// Remove cached recordings
+    Clear {
+        /// Only remove entries of modified or deleted files and of other versions
+        #[arg(long)]
+        stale: bool,
+    },
+}
 
-    match &cli.command {
-        Commands::Convert { input, output: _ } => {
-            let s = std::fs::read_to_string("test_files/Reaction_144_0.asc")?;
+fn convert(
+    input: &[PathBuf],
+    output: &Path,
+    format: Option<OutputFormat>,
+    compress: bool,
+    cache: bool,
+) -> Result<()> {
+    let single = input.len() == 1 && !output.is_dir();
+    let format = match format {
+        Some(format) => format,
+        None if single => OutputFormat::from_path(output).ok_or_else(|| {
+            anyhow!(
+                "cannot infer the format of {}, use --format",
+                output.display()
+            )
+        })?,
+        None => bail!("--format is required when converting several files"),
+    };
+    // `.json.gz` and the like imply compression
+    let compress = compress || (single && output.extension().is_some_and(|e| e == "gz"));
 
-            let lines: Vec<&str> = s.lines().collect();
-            let _nlines = lines.len();
+    let targets = if single {
+        vec![output.to_path_buf()]
+    } else {
+        let targets = format.batch_targets(input, output, compress)?;
+        std::fs::create_dir_all(output)?;
+        targets
+    };
+    let cache = cache.then(ParseCache::default);
 
-            let res: Result<Vec<Element>> = lines
-                .into_par_iter()
-                .progress()
-                .map(Element::from_str)
-                .collect();
-            let gd = Experiment::from(res?);
+    let failures: Vec<(&PathBuf, anyhow::Error)> = input
+        .par_iter()
+        .zip(&targets)
+        .progress_count(input.len() as u64)
+        .filter_map(|(path, target)| {
+            let exp = match &cache {
+                Some(cache) => cache.load(path),
+                None => Experiment::load(path),
+            };
+            exp.and_then(|exp| format.save(&exp, target, compress))
+                .err()
+                .map(|e| (path, e))
+        })
+        .collect();
 
-            for t in &gd.trials {
-                let t_lens: HashMap<String, usize> = t
-                    .targets
-                    .iter()
-                    .map(|(name, info)| (name.clone(), info.len()))
-                    .collect();
-                println!(
-                    "Trial {}, n-samples: {}, variables: {:?}, targets: {:?}",
-                    t.id,
-                    t.samples.len(),
-                    t.variables,
-                    t_lens,
-                );
-            }
+    for (path, e) in &failures {
+        eprintln!("{}: {e:#}", path.display());
+    }
+    if !failures.is_empty() {
+        bail!(
+            "{} of {} files failed to convert",
+            failures.len(),
+            input.len()
+        );
+    }
+    Ok(())
+}
+
+fn list_cache(cache: &ParseCache) -> Result<()> {
+    let entries = cache.entries()?;
+    for (entry, size) in &entries {
+        let status = if entry.is_valid() { "valid" } else { "stale" };
+        println!(
+            "{status}\t{:>10.1} MB\t{}",
+            *size as f64 / 1e6,
+            entry.source.display()
+        );
+    }
+    let total: u64 = entries.iter().map(|(_, size)| size).sum();
+    println!(
+        "{} entries, {:.1} MB in {}",
+        entries.len(),
+        total as f64 / 1e6,
+        cache.dir().display()
+    );
+    Ok(())
+}
+
+fn anonymize(
+    input: &Path,
+    output: &Path,
+    options: &AnonymizeOptions,
+    log: Option<&Path>,
+) -> Result<()> {
+    let format = OutputFormat::from_path(output)
+        .ok_or_else(|| anyhow!("cannot infer the format of {}", output.display()))?;
+    let mut exp = Experiment::load(input)?;
+    let changes = exp.anonymize(options)?;
+    format.save(&exp, output, output.extension().is_some_and(|e| e == "gz"))?;
+
+    let log = log.map_or_else(
+        || {
+            let mut name = output.as_os_str().to_owned();
+            name.push(".log");
+            PathBuf::from(name)
+        },
+        Path::to_path_buf,
+    );
+    let mut text = format!("{} -> {}\n", input.display(), output.display());
+    for change in &changes {
+        text.push_str(change);
+        text.push('\n');
+    }
+    std::fs::write(&log, text)?;
+    println!("{} changes, logged to {}", changes.len(), log.display());
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
 
-            let out = File::create("test_files/out.cbor")?;
-            let wr = BufWriter::new(out);
-            ciborium::ser::into_writer(&gd, wr)?;
+    match &cli.command {
+        Commands::Convert {
+            input,
+            output,
+            format,
+            compress,
+            cache,
+        } => convert(input, output, *format, *compress, *cache)?,
+        Commands::Cache { dir, command } => {
+            let cache = dir
+                .as_ref()
+                .map_or_else(ParseCache::default, ParseCache::new);
+            match command {
+                CacheCommand::List => list_cache(&cache)?,
+                CacheCommand::Clear { stale } => {
+                    let removed = cache.clear(*stale)?;
+                    println!("removed {removed} entries from {}", cache.dir().display());
+                }
+            }
+        }
+        Commands::Anonymize {
+            input,
+            output,
+            strip_dates,
+            shift_days,
+            keep_paths,
+            redact,
+            replacement,
+            rebase_time,
+            log,
+        } => {
+            let dates = match (strip_dates, shift_days) {
+                (true, _) => DateHandling::Strip,
+                (false, Some(days)) => DateHandling::ShiftDays(*days),
+                (false, None) => DateHandling::Keep,
+            };
+            let rules = redact
+                .iter()
+                .map(|pattern| RedactionRule::new(pattern, replacement))
+                .collect::<Result<_>>()?;
+            let options = AnonymizeOptions {
+                dates,
+                scrub_paths: !keep_paths,
+                rules,
+                rebase_time: *rebase_time,
+            };
+            anonymize(input, output, &options, log.as_deref())?;
         }
         Commands::Gui => {
             gui::run().expect("error");
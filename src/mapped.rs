@@ -0,0 +1,221 @@
+//! Zero-copy access to experiment containers.
+//!
+//! [`MappedExperiment`] memory-maps an uncompressed chunked container written by
+//! [`crate::chunked::save_chunked`] and exposes the archived trials in place, so only the pages
+//! of the trials that are actually read are loaded from disk. The mapping is read-only and can
+//! be shared between threads.
+//!
+//! [`MappedExperiment::open`] only reads the header and the trial index. Each trial is validated
+//! the first time it is accessed, so opening does not depend on the size of the trials. Files
+//! that are trusted, e.g. written by this process, can skip the validation of the trials with
+//! the unsafe [`MappedExperiment::open_unchecked`].
+
+use crate::chunked::{ChunkIndex, ChunkedReader, TrialEntry};
+use crate::container::Header;
+use crate::generic::{ArchivedTrial, Experiment, MetaData, Trial};
+use anyhow::{anyhow, bail, Context};
+use memmap2::Mmap;
+use rkyv::{Deserialize, Infallible};
+use std::fs::File;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::OnceLock;
+
+pub struct MappedExperiment {
+    mmap: Mmap,
+    header: Header,
+    index: ChunkIndex,
+    /// Position of the first chunk in the file
+    data_offset: usize,
+    /// Set once the archive of the trial at the same position has been validated
+    validated: Vec<OnceLock<()>>,
+}
+
+impl MappedExperiment {
+    /// Maps the container at `path` and reads its index. The trials are validated when they are
+    /// first accessed.
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        Self::map(path).with_context(|| format!("Could not map {}", path.display()))
+    }
+
+    /// Maps the container at `path` without validating the archives of the trials. The header
+    /// and index are checked like in [`MappedExperiment::open`].
+    ///
+    /// # Safety
+    ///
+    /// The file must be a container written by this version of the library and must not be
+    /// modified while it is mapped. Accessing an invalid archive is undefined behaviour.
+    pub unsafe fn open_unchecked<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let mapped = Self::open(path)?;
+        for validated in &mapped.validated {
+            let _ = validated.set(());
+        }
+        Ok(mapped)
+    }
+
+    fn map(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        // Safety: the mapping is read-only, changes to the file by other processes are outside
+        // of what can be guarded against here, like for any other memory-mapped reader
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if Header::parse(&mmap)?.is_some_and(|(header, _)| !header.chunked || header.compressed) {
+            bail!("Only uncompressed chunked containers can be mapped, save it again to map it");
+        }
+        // Checks the header, the index and that all chunks lie within the file
+        let (header, index, data_offset) = ChunkedReader::new(Cursor::new(&mmap[..]))?.into_parts();
+        let validated = index.trials.iter().map(|_| OnceLock::new()).collect();
+        Ok(MappedExperiment {
+            mmap,
+            header,
+            index,
+            data_offset: data_offset as usize,
+            validated,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn meta(&self) -> &MetaData {
+        &self.index.meta
+    }
+
+    pub fn variable_labels(&self) -> &[String] {
+        &self.index.variable_labels
+    }
+
+    pub fn entries(&self) -> &[TrialEntry] {
+        &self.index.trials
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.trials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.trials.is_empty()
+    }
+
+    /// Position of the trial with the given id.
+    pub fn position(&self, id: u32) -> Option<usize> {
+        self.index.trials.iter().position(|t| t.id == id)
+    }
+
+    /// The archived trial at `index`, validated on first access.
+    pub fn trial(&self, index: usize) -> anyhow::Result<&ArchivedTrial> {
+        let Some(entry) = self.index.trials.get(index) else {
+            bail!(
+                "Trial index {index} out of range, the container has {} trials",
+                self.index.trials.len()
+            );
+        };
+        // The chunk was checked to lie within the file when the index was read
+        let start = self.data_offset + entry.offset as usize;
+        let chunk = &self.mmap[start..start + entry.length as usize];
+        if self.validated[index].get().is_none() {
+            // The mapping is page aligned, so this is the alignment of the chunk in the file
+            if !start.is_multiple_of(16) {
+                bail!(
+                    "Trial {} is not aligned, save the file again to map it",
+                    entry.id
+                );
+            }
+            rkyv::check_archived_root::<Trial>(chunk)
+                .map_err(|e| anyhow!("Invalid archive of trial {}: {e}", entry.id))?;
+            let _ = self.validated[index].set(());
+        }
+        // Safety: the archive was validated above or before, or the caller of `open_unchecked`
+        // vouched for it
+        Ok(unsafe { rkyv::archived_root::<Trial>(chunk) })
+    }
+
+    /// Deserializes the trial at `index` into owned memory.
+    pub fn load_trial(&self, index: usize) -> anyhow::Result<Trial> {
+        Ok(self.trial(index)?.deserialize(&mut Infallible).unwrap())
+    }
+
+    /// Deserializes the whole experiment into owned memory.
+    pub fn to_experiment(&self) -> anyhow::Result<Experiment> {
+        Ok(Experiment {
+            meta: self.index.meta.clone(),
+            variable_labels: self.index.variable_labels.clone(),
+            trials: (0..self.len())
+                .map(|i| self.load_trial(i))
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunked::save_chunked;
+    use crate::container::save_experiment;
+    use crate::generic::{MetaData, Sample, TimeRecord};
+    use crate::Decimal;
+    use rayon::prelude::*;
+
+    #[test]
+    fn test_mapped_access() {
+        let exp = Experiment {
+            meta: MetaData::default(),
+            variable_labels: vec!["id".to_string()],
+            trials: (1..=4)
+                .map(|id| Trial {
+                    samples: (0..id * 10)
+                        .map(|t| Sample {
+                            time: Decimal::from(t),
+                            left: None,
+                            right: None,
+                            resolution: None,
+                            interpolated: false,
+                        })
+                        .collect(),
+                    variables: vec![id.to_string()],
+                    ..Trial::empty(id, TimeRecord::default())
+                })
+                .collect(),
+        };
+        let path = std::env::temp_dir().join(format!("ascc-mapped-{}.dat", std::process::id()));
+
+        save_chunked(&path, &exp, false).unwrap();
+        let mapped = MappedExperiment::open(&path).unwrap();
+        assert_eq!(mapped.len(), 4);
+        assert_eq!(mapped.variable_labels(), ["id"]);
+        let third = mapped.trial(mapped.position(3).unwrap()).unwrap();
+        assert_eq!(third.samples.len(), 30);
+        assert_eq!(mapped.load_trial(1).unwrap().variables, ["2"]);
+        let counts: Vec<usize> = (0..mapped.len())
+            .into_par_iter()
+            .map(|i| mapped.trial(i).unwrap().samples.len())
+            .collect();
+        assert_eq!(counts, [10, 20, 30, 40]);
+        assert!(mapped.trial(4).is_err());
+
+        // Safety: written above and not modified while mapped
+        let unchecked = unsafe { MappedExperiment::open_unchecked(&path) }.unwrap();
+        assert_eq!(unchecked.load_trial(3).unwrap().samples.len(), 40);
+        drop((mapped, unchecked));
+
+        // A corrupt trial is only an error when it is accessed
+        let mut bytes = std::fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last - 64..=last].fill(0xff);
+        std::fs::write(&path, &bytes).unwrap();
+        let mapped = MappedExperiment::open(&path).unwrap();
+        assert!(mapped.load_trial(0).is_ok());
+        assert!(mapped.trial(3).is_err());
+
+        std::fs::write(&path, &bytes[..40]).unwrap();
+        assert!(MappedExperiment::open(&path).is_err());
+
+        save_chunked(&path, &exp, true).unwrap();
+        assert!(MappedExperiment::open(&path).is_err());
+        save_experiment(&path, &exp, false).unwrap();
+        assert!(MappedExperiment::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,659 @@
+//! MATLAB MAT-file (level 5) export.
+//!
+//! An [`Experiment`] is written as a single struct variable:
+//!
+//! * `meta`: recording date, sampling rate, recorded eyes, gaze coordinates and preamble.
+//! * `variable_labels`: cell array of the trial variable labels.
+//! * `sample_columns`, `raw_sample_columns`: cell arrays naming the matrix columns.
+//! * `trials`: 1xN struct array with the fields `id`, `start`, `end`, `samples` (matrix),
+//!   `raw_samples` (matrix), `events` (struct of column vectors, one row per event, can be
+//!   turned into a table with `struct2table`), `messages` (struct of column vectors),
+//!   `variables` (cell array) and `targets` (struct with one `[time x y]` matrix per target).
+//!
+//! Missing values are written as `NaN`. Elements are limited to 4 GB, as in the format.
+
+use crate::common::Eye;
+use crate::decimal_to_f64;
+use crate::generic::{EventInfo, Experiment, Trial};
+use crate::Decimal;
+use anyhow::bail;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const MI_INT8: u32 = 1;
+const MI_UINT16: u32 = 4;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_DOUBLE: u32 = 9;
+const MI_MATRIX: u32 = 14;
+const MI_COMPRESSED: u32 = 15;
+
+const MX_CELL_CLASS: u32 = 1;
+const MX_STRUCT_CLASS: u32 = 2;
+const MX_CHAR_CLASS: u32 = 4;
+const MX_DOUBLE_CLASS: u32 = 6;
+
+/// Longest field name accepted by MATLAB
+const MAX_NAME_LENGTH: usize = 63;
+
+/// A MATLAB array. Data of multidimensional arrays is stored in column-major order.
+#[derive(Debug, Clone)]
+pub enum MatValue {
+    Double {
+        dims: Vec<usize>,
+        data: Vec<f64>,
+    },
+    Char(String),
+    Cell {
+        dims: Vec<usize>,
+        items: Vec<MatValue>,
+    },
+    Struct {
+        dims: Vec<usize>,
+        fields: Vec<String>,
+        /// Field values of each struct element, in the order of `fields`
+        elements: Vec<Vec<MatValue>>,
+    },
+}
+
+impl MatValue {
+    pub fn scalar(value: f64) -> Self {
+        MatValue::Double {
+            dims: vec![1, 1],
+            data: vec![value],
+        }
+    }
+
+    pub fn row(data: Vec<f64>) -> Self {
+        MatValue::Double {
+            dims: vec![1, data.len()],
+            data,
+        }
+    }
+
+    pub fn column(data: Vec<f64>) -> Self {
+        MatValue::Double {
+            dims: vec![data.len(), 1],
+            data,
+        }
+    }
+
+    /// Matrix from rows of equal length.
+    pub fn matrix(rows: &[Vec<f64>], columns: usize) -> Self {
+        let mut data = Vec::with_capacity(rows.len() * columns);
+        for c in 0..columns {
+            data.extend(rows.iter().map(|r| r.get(c).copied().unwrap_or(f64::NAN)));
+        }
+        MatValue::Double {
+            dims: vec![rows.len(), columns],
+            data,
+        }
+    }
+
+    pub fn cell_row<S: AsRef<str>>(items: &[S]) -> Self {
+        MatValue::Cell {
+            dims: vec![1, items.len()],
+            items: items
+                .iter()
+                .map(|s| MatValue::Char(s.as_ref().to_string()))
+                .collect(),
+        }
+    }
+
+    pub fn cell_column<S: AsRef<str>>(items: &[S]) -> Self {
+        MatValue::Cell {
+            dims: vec![items.len(), 1],
+            items: items
+                .iter()
+                .map(|s| MatValue::Char(s.as_ref().to_string()))
+                .collect(),
+        }
+    }
+
+    /// 1x1 struct. Field names are turned into valid, unique MATLAB identifiers.
+    pub fn scalar_struct(fields: Vec<(String, MatValue)>) -> Self {
+        let (names, values): (Vec<String>, Vec<MatValue>) = fields.into_iter().unzip();
+        MatValue::Struct {
+            dims: vec![1, 1],
+            fields: field_names(&names),
+            elements: vec![values],
+        }
+    }
+}
+
+/// Turns arbitrary labels into valid, unique MATLAB field names.
+pub fn field_names<S: AsRef<str>>(labels: &[S]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::with_capacity(labels.len());
+    for label in labels {
+        let mut name: String = label
+            .as_ref()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            name.insert(0, 'x');
+        }
+        name.truncate(MAX_NAME_LENGTH);
+
+        let base = name.clone();
+        let mut i = 1;
+        while names.contains(&name) {
+            let suffix = format!("_{i}");
+            name = format!(
+                "{}{suffix}",
+                &base[..base.len().min(MAX_NAME_LENGTH - suffix.len())]
+            );
+            i += 1;
+        }
+        names.push(name);
+    }
+    names
+}
+
+fn push_element(out: &mut Vec<u8>, data_type: u32, data: &[u8]) {
+    if !data.is_empty() && data.len() <= 4 {
+        // Small data element format, the size is packed into the tag
+        out.extend((data_type | (data.len() as u32) << 16).to_le_bytes());
+        out.extend(data);
+        out.resize(out.len() + 4 - data.len(), 0);
+    } else {
+        out.extend(data_type.to_le_bytes());
+        out.extend((data.len() as u32).to_le_bytes());
+        out.extend(data);
+        out.resize(out.len() + (8 - data.len() % 8) % 8, 0);
+    }
+}
+
+fn push_matrix(out: &mut Vec<u8>, name: &str, value: &MatValue) -> anyhow::Result<()> {
+    let mut body = Vec::new();
+
+    let (class, dims) = match value {
+        MatValue::Double { dims, .. } => (MX_DOUBLE_CLASS, dims.clone()),
+        MatValue::Char(s) => {
+            let len = s.encode_utf16().count();
+            (
+                MX_CHAR_CLASS,
+                if len == 0 { vec![0, 0] } else { vec![1, len] },
+            )
+        }
+        MatValue::Cell { dims, .. } => (MX_CELL_CLASS, dims.clone()),
+        MatValue::Struct { dims, .. } => (MX_STRUCT_CLASS, dims.clone()),
+    };
+
+    let flags: Vec<u8> = [class, 0].iter().flat_map(|v| v.to_le_bytes()).collect();
+    push_element(&mut body, MI_UINT32, &flags);
+    let dims: Vec<u8> = dims
+        .iter()
+        .flat_map(|&d| (d as i32).to_le_bytes())
+        .collect();
+    push_element(&mut body, MI_INT32, &dims);
+    push_element(&mut body, MI_INT8, name.as_bytes());
+
+    match value {
+        MatValue::Double { data, .. } => {
+            let bytes: Vec<u8> = data.iter().flat_map(|v| v.to_le_bytes()).collect();
+            push_element(&mut body, MI_DOUBLE, &bytes);
+        }
+        MatValue::Char(s) => {
+            let bytes: Vec<u8> = s.encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+            push_element(&mut body, MI_UINT16, &bytes);
+        }
+        MatValue::Cell { items, .. } => {
+            for item in items {
+                push_matrix(&mut body, "", item)?;
+            }
+        }
+        MatValue::Struct {
+            fields, elements, ..
+        } => {
+            let length = fields.iter().map(|f| f.len()).max().unwrap_or(0) + 1;
+            push_element(&mut body, MI_INT32, &(length as i32).to_le_bytes());
+            let mut names = Vec::with_capacity(length * fields.len());
+            for field in fields {
+                names.extend(field.as_bytes());
+                names.resize(names.len() + length - field.len(), 0);
+            }
+            push_element(&mut body, MI_INT8, &names);
+            for element in elements {
+                if element.len() != fields.len() {
+                    bail!(
+                        "Struct element has {} values for {} fields",
+                        element.len(),
+                        fields.len()
+                    );
+                }
+                for v in element {
+                    push_matrix(&mut body, "", v)?;
+                }
+            }
+        }
+    }
+
+    if body.len() > u32::MAX as usize {
+        bail!("Variable {name} exceeds the 4 GB element limit of MAT-files");
+    }
+    out.extend(MI_MATRIX.to_le_bytes());
+    out.extend((body.len() as u32).to_le_bytes());
+    out.extend(body);
+    Ok(())
+}
+
+/// Writes a MAT-file holding the given variables, compressing each with zlib if `compress`.
+pub fn write_mat<W: Write>(
+    mut writer: W,
+    variables: &[(&str, &MatValue)],
+    compress: bool,
+) -> anyhow::Result<()> {
+    let mut header = format!(
+        "MATLAB 5.0 MAT-file, Created by: asc-tools {}",
+        env!("CARGO_PKG_VERSION")
+    )
+    .into_bytes();
+    header.resize(116, b' ');
+    header.extend([0; 8]);
+    header.extend(0x0100u16.to_le_bytes());
+    header.extend(b"IM");
+    writer.write_all(&header)?;
+
+    for (name, value) in variables {
+        let mut matrix = Vec::new();
+        push_matrix(&mut matrix, name, value)?;
+        if compress {
+            let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+            e.write_all(&matrix)?;
+            let compressed = e.finish()?;
+            writer.write_all(&MI_COMPRESSED.to_le_bytes())?;
+            writer.write_all(&(compressed.len() as u32).to_le_bytes())?;
+            writer.write_all(&compressed)?;
+        } else {
+            writer.write_all(&matrix)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn f(value: Decimal) -> f64 {
+    decimal_to_f64(value)
+}
+
+fn maybe_f(value: Option<Decimal>) -> f64 {
+    value.map_or(f64::NAN, decimal_to_f64)
+}
+
+const SAMPLE_COLUMNS: [&str; 14] = [
+    "time",
+    "left_pos_x",
+    "left_pos_y",
+    "left_area",
+    "right_pos_x",
+    "right_pos_y",
+    "right_area",
+    "left_vel_x",
+    "left_vel_y",
+    "right_vel_x",
+    "right_vel_y",
+    "res_x",
+    "res_y",
+    "interpolated",
+];
+
+const RAW_SAMPLE_COLUMNS: [&str; 17] = [
+    "time",
+    "left_pupil_pos_x",
+    "left_pupil_pos_y",
+    "left_pupil_area",
+    "left_pupil_size_x",
+    "left_pupil_size_y",
+    "left_cr_pos_x",
+    "left_cr_pos_y",
+    "left_cr_area",
+    "right_pupil_pos_x",
+    "right_pupil_pos_y",
+    "right_pupil_area",
+    "right_pupil_size_x",
+    "right_pupil_size_y",
+    "right_cr_pos_x",
+    "right_cr_pos_y",
+    "right_cr_area",
+];
+
+impl Trial {
+    fn samples_matrix(&self) -> MatValue {
+        let rows: Vec<Vec<f64>> = self
+            .samples
+            .iter()
+            .map(|s| {
+                let pos = |eye: Eye, i: usize| maybe_f(s.eye(eye).map(|e| e.position[i]));
+                let area = |eye: Eye| maybe_f(s.eye(eye).map(|e| e.area));
+                let vel =
+                    |eye: Eye, i: usize| maybe_f(s.eye(eye).and_then(|e| e.velocity).map(|v| v[i]));
+                vec![
+                    f(s.time),
+                    pos(Eye::Left, 0),
+                    pos(Eye::Left, 1),
+                    area(Eye::Left),
+                    pos(Eye::Right, 0),
+                    pos(Eye::Right, 1),
+                    area(Eye::Right),
+                    vel(Eye::Left, 0),
+                    vel(Eye::Left, 1),
+                    vel(Eye::Right, 0),
+                    vel(Eye::Right, 1),
+                    maybe_f(s.resolution.map(|r| r[0])),
+                    maybe_f(s.resolution.map(|r| r[1])),
+                    if s.interpolated { 1. } else { 0. },
+                ]
+            })
+            .collect();
+        MatValue::matrix(&rows, SAMPLE_COLUMNS.len())
+    }
+
+    fn raw_samples_matrix(&self) -> MatValue {
+        let rows: Vec<Vec<f64>> = self
+            .raw_samples
+            .iter()
+            .map(|s| {
+                let mut row = vec![f(s.time)];
+                for e in [&s.left, &s.right] {
+                    row.extend(
+                        [
+                            e.pupil_position[0],
+                            e.pupil_position[1],
+                            e.pupil_area,
+                            e.pupil_size[0],
+                            e.pupil_size[1],
+                            e.cr_position[0],
+                            e.cr_position[1],
+                            e.cr_area,
+                        ]
+                        .map(f),
+                    );
+                }
+                row
+            })
+            .collect();
+        MatValue::matrix(&rows, RAW_SAMPLE_COLUMNS.len())
+    }
+
+    fn events_struct(&self) -> MatValue {
+        let events = &self.events;
+        let column = |g: &dyn Fn(&EventInfo) -> Option<f64>| {
+            MatValue::column(
+                events
+                    .iter()
+                    .map(|e| g(&e.info).unwrap_or(f64::NAN))
+                    .collect(),
+            )
+        };
+        let eyes: Vec<&str> = events.iter().map(|e| e.eye.name()).collect();
+        let kinds: Vec<&str> = events.iter().map(|e| e.info.kind()).collect();
+
+        MatValue::scalar_struct(vec![
+            ("eye".into(), MatValue::cell_column(&eyes)),
+            ("kind".into(), MatValue::cell_column(&kinds)),
+            (
+                "start".into(),
+                MatValue::column(events.iter().map(|e| f(e.time_record.start)).collect()),
+            ),
+            (
+                "end".into(),
+                MatValue::column(events.iter().map(|e| f(e.time_record.end)).collect()),
+            ),
+            (
+                "res_x".into(),
+                MatValue::column(
+                    events
+                        .iter()
+                        .map(|e| maybe_f(e.resolution.map(|r| r[0])))
+                        .collect(),
+                ),
+            ),
+            (
+                "res_y".into(),
+                MatValue::column(
+                    events
+                        .iter()
+                        .map(|e| maybe_f(e.resolution.map(|r| r[1])))
+                        .collect(),
+                ),
+            ),
+            (
+                "average_pos_x".into(),
+                column(&|i| match i {
+                    EventInfo::Fixation {
+                        average_position, ..
+                    } => Some(f(average_position[0])),
+                    _ => None,
+                }),
+            ),
+            (
+                "average_pos_y".into(),
+                column(&|i| match i {
+                    EventInfo::Fixation {
+                        average_position, ..
+                    } => Some(f(average_position[1])),
+                    _ => None,
+                }),
+            ),
+            (
+                "average_pupil_area".into(),
+                column(&|i| match i {
+                    EventInfo::Fixation {
+                        average_pupil_area, ..
+                    } => Some(f(*average_pupil_area)),
+                    _ => None,
+                }),
+            ),
+            (
+                "start_pos_x".into(),
+                column(&|i| match i {
+                    EventInfo::Saccade { start_position, .. } => start_position.map(|p| f(p[0])),
+                    _ => None,
+                }),
+            ),
+            (
+                "start_pos_y".into(),
+                column(&|i| match i {
+                    EventInfo::Saccade { start_position, .. } => start_position.map(|p| f(p[1])),
+                    _ => None,
+                }),
+            ),
+            (
+                "end_pos_x".into(),
+                column(&|i| match i {
+                    EventInfo::Saccade { end_position, .. } => end_position.map(|p| f(p[0])),
+                    _ => None,
+                }),
+            ),
+            (
+                "end_pos_y".into(),
+                column(&|i| match i {
+                    EventInfo::Saccade { end_position, .. } => end_position.map(|p| f(p[1])),
+                    _ => None,
+                }),
+            ),
+            (
+                "movement_angle".into(),
+                column(&|i| match i {
+                    EventInfo::Saccade { movement_angle, .. } => movement_angle.map(f),
+                    _ => None,
+                }),
+            ),
+            (
+                "peak_velocity".into(),
+                column(&|i| match i {
+                    EventInfo::Saccade { peak_velocity, .. } => Some(f(*peak_velocity)),
+                    _ => None,
+                }),
+            ),
+        ])
+    }
+
+    fn targets_struct(&self) -> MatValue {
+        let mut names: Vec<&String> = self.targets.keys().collect();
+        names.sort();
+        MatValue::scalar_struct(
+            names
+                .into_iter()
+                .map(|name| {
+                    let rows: Vec<Vec<f64>> = self.targets[name]
+                        .iter()
+                        .map(|t| vec![f(t.time), t.position[0] as f64, t.position[1] as f64])
+                        .collect();
+                    (name.clone(), MatValue::matrix(&rows, 3))
+                })
+                .collect(),
+        )
+    }
+
+    fn to_mat_fields(&self) -> Vec<MatValue> {
+        let texts: Vec<&str> = self.messages.iter().map(|m| m.text.as_str()).collect();
+        vec![
+            MatValue::scalar(self.id as f64),
+            MatValue::scalar(f(self.time_record.start)),
+            MatValue::scalar(f(self.time_record.end)),
+            self.samples_matrix(),
+            self.raw_samples_matrix(),
+            self.events_struct(),
+            MatValue::scalar_struct(vec![
+                (
+                    "time".into(),
+                    MatValue::column(self.messages.iter().map(|m| f(m.time)).collect()),
+                ),
+                ("text".into(), MatValue::cell_column(&texts)),
+            ]),
+            MatValue::cell_row(&self.variables),
+            self.targets_struct(),
+        ]
+    }
+}
+
+impl Experiment {
+    pub fn to_mat_value(&self) -> MatValue {
+        let meta = &self.meta;
+        let eyes: Vec<&str> = meta.recorded_eyes.iter().map(|e| e.name()).collect();
+        let meta = MatValue::scalar_struct(vec![
+            (
+                "recording_datetime".into(),
+                MatValue::Char(meta.recording_datetime.to_string()),
+            ),
+            (
+                "sampling_rate".into(),
+                MatValue::scalar(maybe_f(meta.sampling_rate)),
+            ),
+            ("recorded_eyes".into(), MatValue::cell_row(&eyes)),
+            (
+                "gaze_coords".into(),
+                MatValue::row(
+                    meta.gaze_coords
+                        .map_or(vec![f64::NAN; 4], |c| c.map(f).to_vec()),
+                ),
+            ),
+            (
+                "preamble".into(),
+                MatValue::cell_column(&meta.preamble_lines),
+            ),
+        ]);
+
+        let trials = MatValue::Struct {
+            dims: vec![1, self.trials.len()],
+            fields: [
+                "id",
+                "start",
+                "end",
+                "samples",
+                "raw_samples",
+                "events",
+                "messages",
+                "variables",
+                "targets",
+            ]
+            .map(String::from)
+            .to_vec(),
+            elements: self.trials.iter().map(|t| t.to_mat_fields()).collect(),
+        };
+
+        MatValue::scalar_struct(vec![
+            ("meta".into(), meta),
+            (
+                "variable_labels".into(),
+                MatValue::cell_row(&self.variable_labels),
+            ),
+            ("sample_columns".into(), MatValue::cell_row(&SAMPLE_COLUMNS)),
+            (
+                "raw_sample_columns".into(),
+                MatValue::cell_row(&RAW_SAMPLE_COLUMNS),
+            ),
+            ("trials".into(), trials),
+        ])
+    }
+
+    /// Writes the experiment as the struct variable `experiment` to a MAT-file.
+    pub fn write_mat<P: AsRef<Path>>(&self, path: P, compress: bool) -> anyhow::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        write_mat(file, &[("experiment", &self.to_mat_value())], compress)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::Read;
+
+    fn example() -> MatValue {
+        MatValue::scalar_struct(vec![
+            ("1st label".into(), MatValue::scalar(1.)),
+            ("1st label".into(), MatValue::cell_row(&["a", ""])),
+            (
+                "m".into(),
+                MatValue::matrix(&[vec![1., 2.], vec![3., 4.]], 2),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_field_names() {
+        assert_eq!(
+            field_names(&["a b", "1x", "a_b", "ok"]),
+            vec!["a_b", "x1x", "a_b_1", "ok"]
+        );
+    }
+
+    #[test]
+    fn test_layout() {
+        let value = example();
+        let mut plain = Vec::new();
+        write_mat(&mut plain, &[("v", &value)], false).unwrap();
+        assert_eq!(&plain[126..128], b"IM");
+
+        let body = &plain[128..];
+        assert_eq!(
+            u32::from_le_bytes(body[0..4].try_into().unwrap()),
+            MI_MATRIX
+        );
+        let size = u32::from_le_bytes(body[4..8].try_into().unwrap()) as usize;
+        assert_eq!(size + 8, body.len());
+        assert_eq!(size % 8, 0);
+
+        let matrix = [[1., 3.], [2., 4.]].concat();
+        let bytes: Vec<u8> = matrix.iter().flat_map(|v: &f64| v.to_le_bytes()).collect();
+        assert!(body.windows(bytes.len()).any(|w| w == bytes));
+
+        let mut compressed = Vec::new();
+        write_mat(&mut compressed, &[("v", &value)], true).unwrap();
+        assert_eq!(
+            u32::from_le_bytes(compressed[128..132].try_into().unwrap()),
+            MI_COMPRESSED
+        );
+        let mut inflated = Vec::new();
+        ZlibDecoder::new(&compressed[136..])
+            .read_to_end(&mut inflated)
+            .unwrap();
+        assert_eq!(inflated, body);
+    }
+}
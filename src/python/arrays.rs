@@ -0,0 +1,215 @@
+//! NumPy arrays of samples, raw samples and camera frames.
+//!
+//! Each column is collected into a `Vec` that NumPy takes over as the buffer of the array, so the
+//! values are not copied again and no Python object is created per sample. Missing values are NaN
+//! in float columns.
+
+use crate::decimal_to_f64;
+use crate::generic::{CRStatus, EyeSampleData, Trial};
+use numpy::IntoPyArray;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+enum Column {
+    F64(Vec<f64>),
+    I8(Vec<i8>),
+    U32(Vec<u32>),
+    U64(Vec<u64>),
+    Bool(Vec<bool>),
+}
+
+impl Column {
+    fn into_numpy(self, py: Python<'_>) -> PyObject {
+        match self {
+            Column::F64(v) => v.into_pyarray(py).into_py(py),
+            Column::I8(v) => v.into_pyarray(py).into_py(py),
+            Column::U32(v) => v.into_pyarray(py).into_py(py),
+            Column::U64(v) => v.into_pyarray(py).into_py(py),
+            Column::Bool(v) => v.into_pyarray(py).into_py(py),
+        }
+    }
+}
+
+/// Named columns of equal length.
+pub struct Columns(Vec<(String, Column)>);
+
+impl Columns {
+    /// Dictionary of column name to NumPy array.
+    pub fn into_dict(self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        let dict = PyDict::new(py);
+        for (name, column) in self.0 {
+            dict.set_item(name, column.into_numpy(py))?;
+        }
+        Ok(dict.into())
+    }
+}
+
+/// Trial ids repeated for each row, for arrays spanning several trials.
+fn trial_ids(trials: &[&Trial], rows: impl Fn(&Trial) -> usize) -> Column {
+    Column::U32(
+        trials
+            .iter()
+            .flat_map(|t| std::iter::repeat(t.id).take(rows(t)))
+            .collect(),
+    )
+}
+
+/// Status of the corneal reflection: -1 if the eye has no data, then 0 missing, 1 recovering
+/// and 2 found.
+fn cr_code(eye: Option<&EyeSampleData>) -> i8 {
+    match eye.map(|e| e.cr) {
+        None => -1,
+        Some(CRStatus::Missing) => 0,
+        Some(CRStatus::Recovering) => 1,
+        Some(CRStatus::Found) => 2,
+    }
+}
+
+pub fn sample_columns(trials: &[&Trial], with_trial_id: bool) -> Columns {
+    let n = trials.iter().map(|t| t.samples.len()).sum();
+    let mut time = Vec::with_capacity(n);
+    let mut eyes: [Vec<Vec<f64>>; 2] =
+        std::array::from_fn(|_| (0..5).map(|_| Vec::with_capacity(n)).collect());
+    let mut cr = [Vec::with_capacity(n), Vec::with_capacity(n)];
+    let mut res = [Vec::with_capacity(n), Vec::with_capacity(n)];
+    let mut interpolated = Vec::with_capacity(n);
+
+    for s in trials.iter().flat_map(|t| &t.samples) {
+        time.push(decimal_to_f64(s.time));
+        for (i, data) in [&s.left, &s.right].into_iter().enumerate() {
+            let values = match data {
+                Some(e) => [
+                    decimal_to_f64(e.position[0]),
+                    decimal_to_f64(e.position[1]),
+                    decimal_to_f64(e.area),
+                    e.velocity.map_or(f64::NAN, |v| decimal_to_f64(v[0])),
+                    e.velocity.map_or(f64::NAN, |v| decimal_to_f64(v[1])),
+                ],
+                None => [f64::NAN; 5],
+            };
+            for (column, v) in eyes[i].iter_mut().zip(values) {
+                column.push(v);
+            }
+            cr[i].push(cr_code(data.as_ref()));
+        }
+        for (column, r) in res.iter_mut().enumerate() {
+            r.push(s.resolution.map_or(f64::NAN, |r| decimal_to_f64(r[column])));
+        }
+        interpolated.push(s.interpolated);
+    }
+
+    let mut columns = Vec::new();
+    if with_trial_id {
+        columns.push((
+            "trial_id".to_string(),
+            trial_ids(trials, |t| t.samples.len()),
+        ));
+    }
+    columns.push(("time".to_string(), Column::F64(time)));
+    let [left, right] = eyes;
+    let [left_cr, right_cr] = cr;
+    for (eye, values, cr) in [("left", left, left_cr), ("right", right, right_cr)] {
+        for (name, v) in ["pos_x", "pos_y", "area", "vel_x", "vel_y"]
+            .iter()
+            .zip(values)
+        {
+            columns.push((format!("{eye}_{name}"), Column::F64(v)));
+        }
+        columns.push((format!("{eye}_cr"), Column::I8(cr)));
+    }
+    let [res_x, res_y] = res;
+    columns.push(("res_x".to_string(), Column::F64(res_x)));
+    columns.push(("res_y".to_string(), Column::F64(res_y)));
+    columns.push(("interpolated".to_string(), Column::Bool(interpolated)));
+    Columns(columns)
+}
+
+pub fn raw_sample_columns(trials: &[&Trial], with_trial_id: bool) -> Columns {
+    const NAMES: [&str; 8] = [
+        "pupil_pos_x",
+        "pupil_pos_y",
+        "pupil_area",
+        "pupil_size_x",
+        "pupil_size_y",
+        "cr_pos_x",
+        "cr_pos_y",
+        "cr_area",
+    ];
+    let n = trials.iter().map(|t| t.raw_samples.len()).sum();
+    let mut time = Vec::with_capacity(n);
+    let mut values: Vec<Vec<f64>> = (0..2 * NAMES.len())
+        .map(|_| Vec::with_capacity(n))
+        .collect();
+
+    for s in trials.iter().flat_map(|t| &t.raw_samples) {
+        time.push(decimal_to_f64(s.time));
+        let row = [&s.left, &s.right].into_iter().flat_map(|e| {
+            [
+                e.pupil_position[0],
+                e.pupil_position[1],
+                e.pupil_area,
+                e.pupil_size[0],
+                e.pupil_size[1],
+                e.cr_position[0],
+                e.cr_position[1],
+                e.cr_area,
+            ]
+        });
+        for (column, v) in values.iter_mut().zip(row) {
+            column.push(decimal_to_f64(v));
+        }
+    }
+
+    let mut columns = Vec::new();
+    if with_trial_id {
+        columns.push((
+            "trial_id".to_string(),
+            trial_ids(trials, |t| t.raw_samples.len()),
+        ));
+    }
+    columns.push(("time".to_string(), Column::F64(time)));
+    let names = ["left", "right"]
+        .iter()
+        .flat_map(|eye| NAMES.map(|name| format!("{eye}_{name}")));
+    columns.extend(names.zip(values.into_iter().map(Column::F64)));
+    Columns(columns)
+}
+
+/// Columns of the camera frames, without the camera names.
+pub fn camera_frame_columns(trials: &[&Trial], with_trial_id: bool) -> Columns {
+    let frames = || trials.iter().flat_map(|t| &t.camera_frames);
+    let mut columns = Vec::new();
+    if with_trial_id {
+        columns.push((
+            "trial_id".to_string(),
+            trial_ids(trials, |t| t.camera_frames.len()),
+        ));
+    }
+    columns.extend([
+        (
+            "idx".to_string(),
+            Column::U32(frames().map(|f| f.idx).collect()),
+        ),
+        (
+            "cam_time".to_string(),
+            Column::U64(frames().map(|f| f.cam_time).collect()),
+        ),
+        (
+            "sys_time".to_string(),
+            Column::U64(frames().map(|f| f.sys_time).collect()),
+        ),
+        (
+            "process_time".to_string(),
+            Column::F64(frames().map(|f| decimal_to_f64(f.process_time)).collect()),
+        ),
+        (
+            "eyelink_time".to_string(),
+            Column::F64(
+                frames()
+                    .map(|f| f.eyelink_time.map_or(f64::NAN, decimal_to_f64))
+                    .collect(),
+            ),
+        ),
+    ]);
+    Columns(columns)
+}
@@ -0,0 +1,93 @@
+use crate::generic::{EventInfo, Position};
+use crate::Decimal;
+use pyo3::prelude::*;
+
+/// Python view of [`EventInfo::Fixation`]
+#[pyclass(get_all)]
+#[derive(Clone)]
+pub struct Fixation {
+    pub average_position: Position,
+    pub average_pupil_area: Decimal,
+}
+
+/// Python view of [`EventInfo::Saccade`]
+#[pyclass(get_all)]
+#[derive(Clone)]
+pub struct Saccade {
+    pub start_position: Option<Position>,
+    pub end_position: Option<Position>,
+    pub movement_angle: Option<Decimal>,
+    pub peak_velocity: Decimal,
+}
+
+/// Python view of [`EventInfo::Blink`]
+#[pyclass]
+#[derive(Clone)]
+pub struct Blink;
+
+#[pymethods]
+impl Fixation {
+    fn __repr__(&self) -> String {
+        format!(
+            "Fixation(average_position=[{}, {}], average_pupil_area={})",
+            self.average_position[0], self.average_position[1], self.average_pupil_area
+        )
+    }
+}
+
+#[pymethods]
+impl Saccade {
+    fn __repr__(&self) -> String {
+        let pos =
+            |p: Option<Position>| p.map_or("None".to_string(), |p| format!("[{}, {}]", p[0], p[1]));
+        format!(
+            "Saccade(start_position={}, end_position={}, movement_angle={}, peak_velocity={})",
+            pos(self.start_position),
+            pos(self.end_position),
+            self.movement_angle
+                .map_or("None".to_string(), |a| a.to_string()),
+            self.peak_velocity
+        )
+    }
+}
+
+#[pymethods]
+impl Blink {
+    fn __repr__(&self) -> &'static str {
+        "Blink()"
+    }
+}
+
+impl ToPyObject for EventInfo {
+    fn to_object(&self, py: Python<'_>) -> PyObject {
+        self.into_py(py)
+    }
+}
+
+impl IntoPy<PyObject> for EventInfo {
+    fn into_py(self, py: Python<'_>) -> PyObject {
+        match self {
+            EventInfo::Fixation {
+                average_position,
+                average_pupil_area,
+            } => Fixation {
+                average_position,
+                average_pupil_area,
+            }
+            .into_py(py),
+            EventInfo::Saccade {
+                start_position,
+                end_position,
+                movement_angle,
+                peak_velocity,
+            } => Saccade {
+                start_position,
+                end_position,
+                movement_angle,
+                peak_velocity,
+            }
+            .into_py(py),
+            EventInfo::Blink => Blink.into_py(py),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! DataFrames for Python.
+//!
+//! The frames of [`crate::export`] are handed to `pyarrow` through the Arrow C stream interface,
+//! so the columns are shared rather than copied into Python lists, and converted to polars or
+//! pandas from there.
+
+use crate::ipc::{ArrowTable, DecimalColumns};
+use polars::prelude::{DataFrame, PolarsResult};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+/// Converts `df` to a DataFrame of `backend` (`polars`, `pandas` or `arrow` for a
+/// `pyarrow.Table`). Decimal columns become `float64` unless `decimals` is `decimal`.
+pub fn to_python(
+    py: Python<'_>,
+    name: &'static str,
+    df: PolarsResult<DataFrame>,
+    backend: &str,
+    decimals: &str,
+) -> PyResult<PyObject> {
+    let decimals = match decimals {
+        "float" => DecimalColumns::Float,
+        "decimal" => DecimalColumns::Decimal,
+        _ => {
+            return Err(PyValueError::new_err(format!(
+                "Invalid decimals {decimals}, expected 'float' or 'decimal'"
+            )))
+        }
+    };
+    if !["polars", "pandas", "arrow"].contains(&backend) {
+        return Err(PyValueError::new_err(format!(
+            "Invalid backend {backend}, expected 'polars', 'pandas' or 'arrow'"
+        )));
+    }
+    let df = df.map_err(anyhow::Error::from)?;
+    let table = ArrowTable::from_frame(name, df, decimals).map_err(anyhow::Error::from)?;
+    let pyarrow = py.import("pyarrow")?;
+
+    let stream = Box::into_raw(Box::new(table.export_to_c()));
+    let reader = pyarrow
+        .getattr("RecordBatchReader")?
+        .call_method1("_import_from_c", (stream as usize,));
+    // pyarrow moves the stream out of the struct on import, otherwise it is still ours to
+    // release. Either way the allocation is freed here.
+    drop(unsafe { Box::from_raw(stream) });
+    let table = reader?.call_method0("read_all")?;
+
+    let frame = match backend {
+        "polars" => py.import("polars")?.call_method1("from_arrow", (table,))?,
+        "pandas" => table.call_method0("to_pandas")?,
+        _ => table,
+    };
+    Ok(frame.into_py(py))
+}
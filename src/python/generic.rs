@@ -1 +1,276 @@
-// impl
+use crate::analysis::quality::TrialQuality;
+use crate::common::Eye;
+use crate::generic::{EventRecord, Experiment, MetaData, Trial};
+use crate::io::OutputFormat;
+use crate::python::arrays::{camera_frame_columns, raw_sample_columns, sample_columns};
+#[cfg(feature = "dataframes")]
+use crate::python::export::to_python;
+use anyhow::{anyhow, bail};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::path::PathBuf;
+
+#[pymethods]
+impl Experiment {
+    /// Loads an experiment in any supported format, detected from the file content, without
+    /// holding the GIL.
+    #[staticmethod]
+    #[pyo3(name = "load")]
+    fn py_load(py: Python<'_>, path: PathBuf) -> PyResult<Experiment> {
+        Ok(py.allow_threads(|| Experiment::load(path))?)
+    }
+
+    /// Saves the experiment, in the format of the file extension unless `format` is given.
+    /// Besides the serialized formats, `mat` writes a MAT-file, `arrow` a directory of Arrow IPC
+    /// tables and `parquet` and `csv` one file per table. A `.gz` extension implies `compress`.
+    #[pyo3(name = "save", signature = (path, format = None, compress = false))]
+    fn py_save(
+        &self,
+        py: Python<'_>,
+        path: PathBuf,
+        format: Option<&str>,
+        compress: bool,
+    ) -> PyResult<()> {
+        let format = match format {
+            Some(format) => format.parse()?,
+            None => OutputFormat::from_path(&path)
+                .ok_or_else(|| anyhow!("Cannot infer the format of {}", path.display()))?,
+        };
+        let compress = compress || path.extension().is_some_and(|e| e == "gz");
+        py.allow_threads(|| format.save(self, &path, compress))?;
+        Ok(())
+    }
+
+    #[pyo3(name = "quality_report")]
+    fn py_quality_report(&self) -> Vec<TrialQuality> {
+        self.quality_report()
+    }
+
+    /// Samples of all trials as a dict of NumPy arrays, see `Trial.sample_arrays`, with an
+    /// additional `trial_id` array.
+    fn sample_arrays(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        let trials: Vec<&Trial> = self.trials.iter().collect();
+        sample_columns(&trials, true).into_dict(py)
+    }
+
+    /// Raw samples of all trials as a dict of NumPy arrays, with a `trial_id` array.
+    fn raw_sample_arrays(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        let trials: Vec<&Trial> = self.trials.iter().collect();
+        raw_sample_columns(&trials, true).into_dict(py)
+    }
+
+    /// Camera frames of all trials as a dict of NumPy arrays, with a `trial_id` array.
+    fn camera_frame_arrays(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        let trials: Vec<&Trial> = self.trials.iter().collect();
+        camera_frame_columns(&trials, true).into_dict(py)
+    }
+
+    /// Trial variables with one row per trial, as a polars, pandas (`backend="pandas"`) or
+    /// pyarrow (`backend="arrow"`) DataFrame. Decimal columns are floats unless
+    /// `decimals="decimal"`.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn trial_variables_df(
+        &self,
+        py: Python<'_>,
+        backend: &str,
+        decimals: &str,
+    ) -> PyResult<PyObject> {
+        to_python(py, "trials", self.trial_variables(), backend, decimals)
+    }
+
+    /// Samples of all trials with `trial_id`, `trial_time` and the trial variables, see
+    /// `trial_variables_df` for the arguments.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn samples_df(&self, py: Python<'_>, backend: &str, decimals: &str) -> PyResult<PyObject> {
+        to_python(py, "samples", self.samples(), backend, decimals)
+    }
+
+    /// Events of all trials with `trial_id`, `trial_time` and the trial variables.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn events_df(&self, py: Python<'_>, backend: &str, decimals: &str) -> PyResult<PyObject> {
+        to_python(py, "events", self.events(), backend, decimals)
+    }
+
+    /// Raw samples of all trials with `trial_id`, `trial_time` and the trial variables.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn raw_samples_df(&self, py: Python<'_>, backend: &str, decimals: &str) -> PyResult<PyObject> {
+        to_python(py, "raw_samples", self.raw_samples(), backend, decimals)
+    }
+
+    /// Target positions of all trials with `trial_id`, `trial_time` and the trial variables.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn targets_df(&self, py: Python<'_>, backend: &str, decimals: &str) -> PyResult<PyObject> {
+        to_python(py, "targets", self.targets(), backend, decimals)
+    }
+
+    /// Camera frames of all trials with `trial_id`, `trial_time` and the trial variables.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn cam_frames_df(&self, py: Python<'_>, backend: &str, decimals: &str) -> PyResult<PyObject> {
+        to_python(py, "cam_frames", self.cam_frames(), backend, decimals)
+    }
+
+    /// Data quality report with one row per trial and recorded eye.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn data_quality_df(&self, py: Python<'_>, backend: &str, decimals: &str) -> PyResult<PyObject> {
+        to_python(py, "data_quality", self.data_quality(), backend, decimals)
+    }
+}
+
+/// Parses `left`/`right` or the ASC shorthands `L`/`R`.
+fn parse_eye(eye: &str) -> anyhow::Result<Eye> {
+    match eye.to_lowercase().as_str() {
+        "left" | "l" => Ok(Eye::Left),
+        "right" | "r" => Ok(Eye::Right),
+        _ => bail!("Invalid eye {eye}, expected 'left' or 'right'"),
+    }
+}
+
+#[pymethods]
+impl Trial {
+    #[pyo3(name = "quality")]
+    fn py_quality(&self, meta: PyRef<MetaData>) -> TrialQuality {
+        self.quality(&meta)
+    }
+
+    /// Samples as a dict of NumPy arrays: `time`, `{left,right}_{pos_x,pos_y,area,vel_x,vel_y}`
+    /// with NaN where an eye or value is missing, `{left,right}_cr` status codes (-1 no data,
+    /// 0 missing, 1 recovering, 2 found), `res_x`, `res_y` and `interpolated`.
+    fn sample_arrays(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        sample_columns(&[self], false).into_dict(py)
+    }
+
+    /// Raw samples as a dict of NumPy arrays: `time` and
+    /// `{left,right}_{pupil_pos_x,pupil_pos_y,pupil_area,pupil_size_x,pupil_size_y,cr_pos_x,cr_pos_y,cr_area}`.
+    fn raw_sample_arrays(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        raw_sample_columns(&[self], false).into_dict(py)
+    }
+
+    /// Camera frames as a dict of NumPy arrays: `idx`, `cam_time`, `sys_time`, `process_time`
+    /// and `eyelink_time` (NaN if missing).
+    fn camera_frame_arrays(&self, py: Python<'_>) -> PyResult<Py<PyDict>> {
+        camera_frame_columns(&[self], false).into_dict(py)
+    }
+
+    /// Samples as a polars, pandas (`backend="pandas"`) or pyarrow (`backend="arrow"`)
+    /// DataFrame. Decimal columns are floats unless `decimals="decimal"`.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn samples_df(&self, py: Python<'_>, backend: &str, decimals: &str) -> PyResult<PyObject> {
+        to_python(py, "samples", self.samples(), backend, decimals)
+    }
+
+    /// Events with one row per event, see `samples_df` for the arguments.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn events_df(&self, py: Python<'_>, backend: &str, decimals: &str) -> PyResult<PyObject> {
+        to_python(py, "events", self.events(), backend, decimals)
+    }
+
+    /// Raw samples, see `samples_df` for the arguments.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn raw_samples_df(&self, py: Python<'_>, backend: &str, decimals: &str) -> PyResult<PyObject> {
+        to_python(py, "raw_samples", self.raw_samples(), backend, decimals)
+    }
+
+    /// Target positions, see `samples_df` for the arguments.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn targets_df(&self, py: Python<'_>, backend: &str, decimals: &str) -> PyResult<PyObject> {
+        to_python(py, "targets", self.targets(), backend, decimals)
+    }
+
+    /// Camera frames, see `samples_df` for the arguments.
+    #[cfg(feature = "dataframes")]
+    #[pyo3(signature = (backend = "polars", decimals = "float"))]
+    fn cam_frames_df(&self, py: Python<'_>, backend: &str, decimals: &str) -> PyResult<PyObject> {
+        to_python(py, "cam_frames", self.cam_frames(), backend, decimals)
+    }
+
+    /// Events of the given kind (`fixation`, `saccade` or `blink`), optionally only those of one
+    /// eye.
+    #[pyo3(signature = (kind = None, eye = None))]
+    fn events_of(&self, kind: Option<&str>, eye: Option<&str>) -> PyResult<Vec<EventRecord>> {
+        if let Some(kind) = kind {
+            if !["fixation", "saccade", "blink"].contains(&kind) {
+                return Err(anyhow!("Invalid event kind {kind}").into());
+            }
+        }
+        let eye = eye.map(parse_eye).transpose()?;
+        Ok(self
+            .events
+            .iter()
+            .filter(|e| kind.map_or(true, |k| e.info.kind() == k))
+            .filter(|e| eye.map_or(true, |eye| e.eye == eye))
+            .copied()
+            .collect())
+    }
+
+    #[pyo3(signature = (eye = None))]
+    fn fixations(&self, eye: Option<&str>) -> PyResult<Vec<EventRecord>> {
+        self.events_of(Some("fixation"), eye)
+    }
+
+    #[pyo3(signature = (eye = None))]
+    fn saccades(&self, eye: Option<&str>) -> PyResult<Vec<EventRecord>> {
+        self.events_of(Some("saccade"), eye)
+    }
+
+    #[pyo3(signature = (eye = None))]
+    fn blinks(&self, eye: Option<&str>) -> PyResult<Vec<EventRecord>> {
+        self.events_of(Some("blink"), eye)
+    }
+}
+
+#[pymethods]
+impl EventRecord {
+    /// `fixation`, `saccade` or `blink`
+    #[getter]
+    fn kind(&self) -> &'static str {
+        self.info.kind()
+    }
+
+    /// Duration in ms
+    #[getter(duration)]
+    fn py_duration(&self) -> f64 {
+        self.duration()
+    }
+
+    #[getter(is_fixation)]
+    fn py_is_fixation(&self) -> bool {
+        self.is_fixation()
+    }
+
+    #[getter(is_saccade)]
+    fn py_is_saccade(&self) -> bool {
+        self.is_saccade()
+    }
+
+    #[getter(is_blink)]
+    fn py_is_blink(&self) -> bool {
+        self.is_blink()
+    }
+
+    /// Saccade amplitude in degrees, `None` for other events or missing positions
+    #[getter(saccade_amplitude)]
+    fn py_saccade_amplitude(&self) -> Option<f64> {
+        self.saccade_amplitude()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "EventRecord(kind={}, eye={}, start={}, end={})",
+            self.info.kind(),
+            self.eye.name(),
+            self.time_record.start,
+            self.time_record.end
+        )
+    }
+}
@@ -0,0 +1,96 @@
+//! Saving and batch conversion for Python. Both run without holding the GIL.
+
+use crate::cache::ParseCache;
+use crate::generic::Experiment;
+use crate::io::OutputFormat;
+use anyhow::{anyhow, bail};
+use pyo3::prelude::*;
+use rayon::prelude::*;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Calls the Python `progress` callback from a thread that released the GIL.
+pub fn report(progress: &Option<PyObject>, done: usize, total: usize) -> anyhow::Result<()> {
+    if let Some(progress) = progress {
+        Python::with_gil(|py| progress.call1(py, (done, total)))?;
+    }
+    Ok(())
+}
+
+/// Converts recordings in any supported format to `format`, writing `<stem>.<ext>` files to
+/// `output_dir`. The files are converted in parallel without holding the GIL, and
+/// `progress(done, total)` is called after each file; an exception raised by it cancels the
+/// remaining files. Returns the written paths, files that failed to convert are reported in one
+/// error after all others are written. Inputs that would be written to the same file, e.g. files
+/// with the same name from different directories, are rejected before any file is converted.
+#[pyfunction]
+#[pyo3(signature = (inputs, output_dir, format, compress = false, cache = false, progress = None))]
+pub fn convert(
+    py: Python<'_>,
+    inputs: Vec<PathBuf>,
+    output_dir: PathBuf,
+    format: &str,
+    compress: bool,
+    cache: bool,
+    progress: Option<PyObject>,
+) -> PyResult<Vec<PathBuf>> {
+    let format: OutputFormat = format.parse()?;
+    let written = py.allow_threads(|| -> anyhow::Result<Vec<PathBuf>> {
+        let targets = format.batch_targets(&inputs, &output_dir, compress)?;
+        std::fs::create_dir_all(&output_dir)?;
+        let cache = cache.then(ParseCache::default);
+        let done = AtomicUsize::new(0);
+        let cancelled: Mutex<Option<anyhow::Error>> = Mutex::new(None);
+
+        let results: Vec<(&PathBuf, anyhow::Result<PathBuf>)> = inputs
+            .par_iter()
+            .zip(targets)
+            .map(|(path, target)| {
+                if cancelled.lock().unwrap().is_some() {
+                    return (path, Err(anyhow!("Cancelled")));
+                }
+                let exp = match &cache {
+                    Some(cache) => cache.load(path),
+                    None => Experiment::load(path),
+                };
+                let result = exp
+                    .and_then(|exp| format.save(&exp, &target, compress))
+                    .map(|()| target);
+                // Counted with the GIL held, so the callback sees increasing counts
+                if let Some(progress) = &progress {
+                    let called = Python::with_gil(|py| {
+                        let n = done.fetch_add(1, Ordering::SeqCst) + 1;
+                        progress.call1(py, (n, inputs.len()))
+                    });
+                    if let Err(e) = called {
+                        cancelled.lock().unwrap().get_or_insert(e.into());
+                    }
+                }
+                (path, result)
+            })
+            .collect();
+
+        if let Some(e) = cancelled.into_inner().unwrap() {
+            return Err(e);
+        }
+        let mut written = Vec::new();
+        let mut failures = Vec::new();
+        for (path, result) in results {
+            match result {
+                Ok(target) => written.push(target),
+                Err(e) => failures.push(format!("{}: {e:#}", path.display())),
+            }
+        }
+        if !failures.is_empty() {
+            bail!(
+                "{} of {} files failed to convert:\n{}",
+                failures.len(),
+                inputs.len(),
+                failures.join("\n")
+            );
+        }
+        Ok(written)
+    })?;
+    Ok(written)
+}
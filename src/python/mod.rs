@@ -1,36 +1,55 @@
+mod arrays;
+mod events;
 mod generic;
+mod io;
 
 #[cfg(feature = "dataframes")]
 mod export;
 
+use crate::analysis::quality::{EyeQuality, TrialQuality};
+use crate::cache::ParseCache;
+use crate::common::Eye;
 use crate::generic::{
-    EventInfo, Experiment, MetaData, RawSample, Sample, TargetInfo, TimeRecord, Trial,
+    EventRecord, Experiment, Message, MetaData, RawSample, Sample, TargetInfo, TimeRecord, Trial,
 };
 use crate::{Decimal, NaiveDateTime};
 use chrono::{Datelike, ParseResult, Timelike};
 use pyo3::prelude::*;
-use pyo3::types::{PyDateTime, PyDict, PyList, PyString};
+use pyo3::types::PyDateTime;
 use rust_decimal::prelude::ToPrimitive;
 use std::fmt::{Display, Formatter};
-use std::fs::File;
-use std::io::{BufReader, Read, Write};
 use std::ops::Sub;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+/// Parses an ASC file without holding the GIL, calling `progress(done, total)` with the number
+/// of parsed lines along the way. With `cache`, the file is only parsed, and `progress` only
+/// called, if there is no valid cache entry.
 #[pyfunction]
-fn load_asc_from_file(path: PathBuf) -> PyResult<Experiment> {
-    let exp = crate::load_asc_from_file_with_progress(path)?;
+#[pyo3(signature = (path, cache = false, progress = None))]
+fn load_asc_from_file(
+    py: Python<'_>,
+    path: PathBuf,
+    cache: bool,
+    progress: Option<PyObject>,
+) -> PyResult<Experiment> {
+    let parse = |path: &Path| {
+        let s = std::fs::read_to_string(path)?;
+        crate::asc_to_generic_with_callback(&s, |done, total| io::report(&progress, done, total))
+    };
+    let exp = py.allow_threads(|| {
+        if cache {
+            ParseCache::default().load_with(&path, parse)
+        } else {
+            parse(&path)
+        }
+    })?;
     Ok(exp)
 }
 
 #[pyfunction]
-fn load_experiment_file(path: PathBuf) -> PyResult<Experiment> {
-    let base = File::open(&path)?;
-
-    let mut bytes = Vec::new();
-    BufReader::new(base).read_to_end(&mut bytes)?;
-    Ok(rkyv::from_bytes(&bytes).unwrap())
+fn load_experiment_file(py: Python<'_>, path: PathBuf) -> PyResult<Experiment> {
+    Ok(py.allow_threads(|| Experiment::load(path))?)
 }
 
 #[pymodule]
@@ -42,9 +61,18 @@ fn ascc(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_class::<TargetInfo>()?;
     m.add_class::<Sample>()?;
     m.add_class::<RawSample>()?;
+    m.add_class::<Message>()?;
+    m.add_class::<EventRecord>()?;
+    m.add_class::<events::Fixation>()?;
+    m.add_class::<events::Saccade>()?;
+    m.add_class::<events::Blink>()?;
+    m.add_class::<Eye>()?;
+    m.add_class::<TrialQuality>()?;
+    m.add_class::<EyeQuality>()?;
 
     m.add_function(wrap_pyfunction!(load_asc_from_file, m)?)?;
     m.add_function(wrap_pyfunction!(load_experiment_file, m)?)?;
+    m.add_function(wrap_pyfunction!(io::convert, m)?)?;
 
     Ok(())
 }
@@ -55,6 +83,12 @@ impl Display for Decimal {
     }
 }
 
+impl Display for NaiveDateTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
 impl ToPyObject for NaiveDateTime {
     fn to_object(&self, py: Python<'_>) -> PyObject {
         PyDateTime::new(
@@ -103,48 +137,6 @@ impl IntoPy<PyObject> for Decimal {
     }
 }
 
-impl ToPyObject for EventInfo {
-    fn to_object(&self, py: Python<'_>) -> PyObject {
-        match self {
-            EventInfo::Fixation {
-                average_position,
-                average_pupil_area,
-            } => {
-                let pos: PyObject = ("average_position", average_position.to_vec()).into_py(py);
-                let area = ("average_pupil_area", average_pupil_area.into_py(py)).into_py(py);
-                let seq = PyList::new(py, [pos, area]).to_object(py);
-                PyDict::from_sequence(py, seq).unwrap().to_object(py)
-            }
-            EventInfo::Saccade {
-                start_position,
-                end_position,
-                movement_angle,
-                peak_velocity,
-            } => {
-                let start_position: PyObject =
-                    ("start_position", start_position.map(|p| p.to_vec())).into_py(py);
-                let end_position = ("end_position", end_position.map(|p| p.to_vec())).into_py(py);
-                let movement_angle =
-                    ("movement_angle", movement_angle.map(|a| a.into_py(py))).into_py(py);
-                let peak_velocity = ("peak_velocity", peak_velocity.into_py(py)).into_py(py);
-                let seq = PyList::new(
-                    py,
-                    [start_position, end_position, movement_angle, peak_velocity],
-                )
-                .to_object(py);
-                PyDict::from_sequence(py, seq).unwrap().to_object(py)
-            }
-            EventInfo::Blink => PyString::new(py, "blink").to_object(py),
-        }
-    }
-}
-
-impl IntoPy<PyObject> for EventInfo {
-    fn into_py(self, _py: Python<'_>) -> PyObject {
-        todo!()
-    }
-}
-
 impl FromStr for Decimal {
     type Err = rust_decimal::Error;
 
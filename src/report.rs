@@ -0,0 +1,558 @@
+//! Sample, fixation and saccade reports in the tab-separated layout of the SR Research Data
+//! Viewer exports, with the same column names so that existing scripts can read them.
+//!
+//! Times named `*_START`/`*_END` are in ms relative to the trial start, `TIMESTAMP` and
+//! `TRIAL_START_TIME` are in the tracker clock. Indices are 1-based and counted per trial and
+//! eye. Missing values are written as `.` and the trial variables are appended as extra
+//! columns named by their labels.
+
+use crate::analysis::scanpath::InterestArea;
+use crate::common::Eye;
+use crate::decimal_to_f64;
+use crate::generic::{EventInfo, EventRecord, Experiment, Trial};
+use crate::Decimal;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default)]
+pub struct ReportOptions {
+    /// Value of the `RECORDING_SESSION_LABEL` column, usually the file name without extension
+    pub session_label: String,
+    /// Interest areas for the `*_INTEREST_AREA_*` columns
+    pub interest_areas: Vec<InterestArea>,
+}
+
+const SAMPLE_COLUMNS: [&str; 30] = [
+    "RECORDING_SESSION_LABEL",
+    "TRIAL_INDEX",
+    "SAMPLE_INDEX",
+    "TIMESTAMP",
+    "EYE_TRACKED",
+    "LEFT_GAZE_X",
+    "LEFT_GAZE_Y",
+    "LEFT_PUPIL_SIZE",
+    "LEFT_VELOCITY_X",
+    "LEFT_VELOCITY_Y",
+    "LEFT_IN_BLINK",
+    "LEFT_IN_SACCADE",
+    "LEFT_FIX_INDEX",
+    "LEFT_SACCADE_INDEX",
+    "LEFT_INTEREST_AREA_LABEL",
+    "RIGHT_GAZE_X",
+    "RIGHT_GAZE_Y",
+    "RIGHT_PUPIL_SIZE",
+    "RIGHT_VELOCITY_X",
+    "RIGHT_VELOCITY_Y",
+    "RIGHT_IN_BLINK",
+    "RIGHT_IN_SACCADE",
+    "RIGHT_FIX_INDEX",
+    "RIGHT_SACCADE_INDEX",
+    "RIGHT_INTEREST_AREA_LABEL",
+    "AVERAGE_GAZE_X",
+    "AVERAGE_GAZE_Y",
+    "RESOLUTION_X",
+    "RESOLUTION_Y",
+    "SAMPLE_MESSAGE",
+];
+
+const FIXATION_COLUMNS: [&str; 19] = [
+    "RECORDING_SESSION_LABEL",
+    "TRIAL_INDEX",
+    "EYE_USED",
+    "CURRENT_FIX_INDEX",
+    "CURRENT_FIX_START",
+    "CURRENT_FIX_END",
+    "CURRENT_FIX_DURATION",
+    "CURRENT_FIX_X",
+    "CURRENT_FIX_Y",
+    "CURRENT_FIX_PUPIL",
+    "CURRENT_FIX_X_RESOLUTION",
+    "CURRENT_FIX_Y_RESOLUTION",
+    "CURRENT_FIX_INTEREST_AREA_INDEX",
+    "CURRENT_FIX_INTEREST_AREA_LABEL",
+    "PREVIOUS_SAC_AMPLITUDE",
+    "NEXT_SAC_AMPLITUDE",
+    "TRIAL_FIXATION_TOTAL",
+    "TRIAL_START_TIME",
+    "TRIAL_DWELL_TIME",
+];
+
+const SACCADE_COLUMNS: [&str; 21] = [
+    "RECORDING_SESSION_LABEL",
+    "TRIAL_INDEX",
+    "EYE_USED",
+    "CURRENT_SAC_INDEX",
+    "CURRENT_SAC_START_TIME",
+    "CURRENT_SAC_END_TIME",
+    "CURRENT_SAC_DURATION",
+    "CURRENT_SAC_START_X",
+    "CURRENT_SAC_START_Y",
+    "CURRENT_SAC_END_X",
+    "CURRENT_SAC_END_Y",
+    "CURRENT_SAC_AMPLITUDE",
+    "CURRENT_SAC_ANGLE",
+    "CURRENT_SAC_PEAK_VELOCITY",
+    "CURRENT_SAC_AVG_VELOCITY",
+    "CURRENT_SAC_CONTAINS_BLINK",
+    "CURRENT_SAC_START_INTEREST_AREA_LABEL",
+    "CURRENT_SAC_END_INTEREST_AREA_LABEL",
+    "TRIAL_SACCADE_TOTAL",
+    "TRIAL_START_TIME",
+    "TRIAL_DWELL_TIME",
+];
+
+fn missing<T: Display>(value: Option<T>) -> String {
+    value.map_or_else(|| ".".to_string(), |v| v.to_string())
+}
+
+fn float(value: Option<f64>) -> String {
+    missing(value.filter(|v| v.is_finite()).map(|v| format!("{v:.2}")))
+}
+
+fn eye_used(eye: Eye) -> &'static str {
+    match eye {
+        Eye::Left => "LEFT",
+        Eye::Right => "RIGHT",
+    }
+}
+
+/// Tabs and newlines would break the columns
+fn field(value: &str) -> String {
+    if value.is_empty() {
+        ".".to_string()
+    } else {
+        value.replace(['\t', '\n', '\r'], " ")
+    }
+}
+
+fn write_header<W: Write>(
+    writer: &mut W,
+    columns: &[&str],
+    labels: &[String],
+) -> anyhow::Result<()> {
+    let labels = labels.iter().map(|l| field(l));
+    let header: Vec<String> = columns
+        .iter()
+        .map(|c| c.to_string())
+        .chain(labels)
+        .collect();
+    writeln!(writer, "{}", header.join("\t"))?;
+    Ok(())
+}
+
+/// Position of the interest area containing `p`, 1-based as in Data Viewer.
+fn interest_area(areas: &[InterestArea], p: Option<[f64; 2]>) -> Option<(usize, &str)> {
+    let p = p?;
+    areas
+        .iter()
+        .enumerate()
+        .find(|(_, a)| a.contains(p))
+        .map(|(i, a)| (i + 1, a.label.as_str()))
+}
+
+/// Events of one kind and eye, in the order they occurred.
+struct Intervals<'a> {
+    events: Vec<&'a EventRecord>,
+}
+
+impl<'a> Intervals<'a> {
+    fn new(trial: &'a Trial, eye: Eye, kind: &str) -> Self {
+        let mut events: Vec<&EventRecord> = trial
+            .events
+            .iter()
+            .filter(|e| e.eye == eye && e.info.kind() == kind)
+            .collect();
+        events.sort_by(|a, b| {
+            decimal_to_f64(a.time_record.start).total_cmp(&decimal_to_f64(b.time_record.start))
+        });
+        Intervals { events }
+    }
+
+    /// 1-based index of the event spanning `time`.
+    fn containing(&self, time: f64) -> Option<usize> {
+        let i = self
+            .events
+            .partition_point(|e| decimal_to_f64(e.time_record.start) <= time);
+        (i > 0 && decimal_to_f64(self.events[i - 1].time_record.end) >= time).then_some(i)
+    }
+
+    fn any_within(&self, start: f64, end: f64) -> bool {
+        self.events.iter().any(|e| {
+            decimal_to_f64(e.time_record.start) <= end && decimal_to_f64(e.time_record.end) >= start
+        })
+    }
+}
+
+struct TrialRows<'a> {
+    trial: &'a Trial,
+    /// Leading columns, session label and trial index
+    prefix: String,
+    /// Trailing columns, the trial variables
+    variables: String,
+    start: f64,
+    dwell: f64,
+}
+
+impl<'a> TrialRows<'a> {
+    fn new(exp: &Experiment, index: usize, trial: &'a Trial, options: &ReportOptions) -> Self {
+        let variables = (0..exp.variable_labels.len())
+            .map(|i| {
+                format!(
+                    "\t{}",
+                    field(trial.variables.get(i).map_or("", |v| v.as_str()))
+                )
+            })
+            .collect();
+        let start = decimal_to_f64(trial.time_record.start);
+        TrialRows {
+            trial,
+            prefix: format!("{}\t{}", field(&options.session_label), index + 1),
+            variables,
+            start,
+            dwell: decimal_to_f64(trial.time_record.end) - start,
+        }
+    }
+
+    fn relative(&self, time: Decimal) -> String {
+        float(Some(decimal_to_f64(time) - self.start))
+    }
+}
+
+impl Experiment {
+    fn trial_rows<'a>(&'a self, options: &ReportOptions) -> Vec<TrialRows<'a>> {
+        self.trials
+            .iter()
+            .enumerate()
+            .map(|(i, t)| TrialRows::new(self, i, t, options))
+            .collect()
+    }
+
+    /// One row per sample.
+    pub fn write_sample_report<W: Write>(
+        &self,
+        mut writer: W,
+        options: &ReportOptions,
+    ) -> anyhow::Result<()> {
+        write_header(&mut writer, &SAMPLE_COLUMNS, &self.variable_labels)?;
+        let areas = &options.interest_areas;
+
+        for rows in self.trial_rows(options) {
+            let trial = rows.trial;
+            let intervals = |eye, kind| Intervals::new(trial, eye, kind);
+            let blinks = [
+                intervals(Eye::Left, "blink"),
+                intervals(Eye::Right, "blink"),
+            ];
+            let saccades = [
+                intervals(Eye::Left, "saccade"),
+                intervals(Eye::Right, "saccade"),
+            ];
+            let fixations = [
+                intervals(Eye::Left, "fixation"),
+                intervals(Eye::Right, "fixation"),
+            ];
+
+            // The eyes of the recording configuration, or those with data in the trial
+            let tracked = |eye| {
+                if self.meta.recorded_eyes.is_empty() {
+                    trial.samples.iter().any(|s| s.eye(eye).is_some())
+                } else {
+                    self.meta.recorded_eyes.contains(&eye)
+                }
+            };
+            let eye_tracked = match (tracked(Eye::Left), tracked(Eye::Right)) {
+                (true, true) => "Binocular",
+                (true, false) => "Left",
+                (false, true) => "Right",
+                (false, false) => ".",
+            };
+
+            // Messages are attached to the first sample at or after them, as in Data Viewer
+            let mut messages: Vec<(f64, &str)> = trial
+                .messages
+                .iter()
+                .map(|m| (decimal_to_f64(m.time), m.text.as_str()))
+                .collect();
+            messages.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let mut messages = messages.into_iter().peekable();
+
+            for (i, s) in trial.samples.iter().enumerate() {
+                let time = decimal_to_f64(s.time);
+                let mut row = format!("{}\t{}\t{}\t{eye_tracked}", rows.prefix, i + 1, s.time);
+
+                let mut positions = Vec::new();
+                for (k, eye) in [Eye::Left, Eye::Right].into_iter().enumerate() {
+                    let data = s.eye(eye);
+                    let position = data.map(|d| d.position.map(decimal_to_f64));
+                    positions.extend(position);
+                    let velocity = data.and_then(|d| d.velocity);
+                    let flag = |b: bool| if b { "1" } else { "0" };
+                    row.push_str(&format!(
+                        "\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                        float(position.map(|p| p[0])),
+                        float(position.map(|p| p[1])),
+                        missing(data.map(|d| d.area)),
+                        missing(velocity.map(|v| v[0])),
+                        missing(velocity.map(|v| v[1])),
+                        flag(blinks[k].containing(time).is_some()),
+                        flag(saccades[k].containing(time).is_some()),
+                        missing(fixations[k].containing(time)),
+                        missing(saccades[k].containing(time)),
+                        field(interest_area(areas, position).map_or("", |a| a.1)),
+                    ));
+                }
+
+                let average = (!positions.is_empty()).then(|| {
+                    let n = positions.len() as f64;
+                    [
+                        positions.iter().map(|p| p[0]).sum::<f64>() / n,
+                        positions.iter().map(|p| p[1]).sum::<f64>() / n,
+                    ]
+                });
+                let mut sample_messages = Vec::new();
+                while let Some((_, text)) = messages.next_if(|m| m.0 <= time) {
+                    sample_messages.push(text);
+                }
+                row.push_str(&format!(
+                    "\t{}\t{}\t{}\t{}\t{}",
+                    float(average.map(|p| p[0])),
+                    float(average.map(|p| p[1])),
+                    missing(s.resolution.map(|r| r[0])),
+                    missing(s.resolution.map(|r| r[1])),
+                    field(&sample_messages.join(" ")),
+                ));
+                writeln!(writer, "{row}{}", rows.variables)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// One row per fixation.
+    pub fn write_fixation_report<W: Write>(
+        &self,
+        mut writer: W,
+        options: &ReportOptions,
+    ) -> anyhow::Result<()> {
+        write_header(&mut writer, &FIXATION_COLUMNS, &self.variable_labels)?;
+
+        for rows in self.trial_rows(options) {
+            for eye in [Eye::Left, Eye::Right] {
+                let fixations = Intervals::new(rows.trial, eye, "fixation").events;
+                let saccades = Intervals::new(rows.trial, eye, "saccade").events;
+                let amplitude =
+                    |e: Option<&&EventRecord>| float(e.and_then(|e| e.saccade_amplitude()));
+
+                for (i, fix) in fixations.iter().enumerate() {
+                    let EventInfo::Fixation {
+                        average_position,
+                        average_pupil_area,
+                    } = fix.info
+                    else {
+                        continue;
+                    };
+                    let position = average_position.map(decimal_to_f64);
+                    let area = interest_area(&options.interest_areas, Some(position));
+                    let start = decimal_to_f64(fix.time_record.start);
+                    let previous = saccades
+                        .iter()
+                        .rev()
+                        .find(|s| decimal_to_f64(s.time_record.end) <= start);
+                    let end = decimal_to_f64(fix.time_record.end);
+                    let next = saccades
+                        .iter()
+                        .find(|s| decimal_to_f64(s.time_record.start) >= end);
+
+                    writeln!(
+                        writer,
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}{}",
+                        rows.prefix,
+                        eye_used(eye),
+                        i + 1,
+                        rows.relative(fix.time_record.start),
+                        rows.relative(fix.time_record.end),
+                        float(Some(fix.duration())),
+                        float(Some(position[0])),
+                        float(Some(position[1])),
+                        average_pupil_area,
+                        missing(fix.resolution.map(|r| r[0])),
+                        missing(fix.resolution.map(|r| r[1])),
+                        missing(area.map(|a| a.0)),
+                        field(area.map_or("", |a| a.1)),
+                        amplitude(previous),
+                        amplitude(next),
+                        fixations.len(),
+                        rows.trial.time_record.start,
+                        float(Some(rows.dwell)),
+                        rows.variables,
+                    )?;
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// One row per saccade.
+    pub fn write_saccade_report<W: Write>(
+        &self,
+        mut writer: W,
+        options: &ReportOptions,
+    ) -> anyhow::Result<()> {
+        write_header(&mut writer, &SACCADE_COLUMNS, &self.variable_labels)?;
+        let areas = &options.interest_areas;
+
+        for rows in self.trial_rows(options) {
+            for eye in [Eye::Left, Eye::Right] {
+                let saccades = Intervals::new(rows.trial, eye, "saccade").events;
+                let blinks = Intervals::new(rows.trial, eye, "blink");
+
+                for (i, sac) in saccades.iter().enumerate() {
+                    let EventInfo::Saccade {
+                        start_position,
+                        end_position,
+                        peak_velocity,
+                        ..
+                    } = sac.info
+                    else {
+                        continue;
+                    };
+                    let start_position = start_position.map(|p| p.map(decimal_to_f64));
+                    let end_position = end_position.map(|p| p.map(decimal_to_f64));
+                    let amplitude = sac.saccade_amplitude();
+                    let duration = sac.duration();
+                    let contains_blink = blinks.any_within(
+                        decimal_to_f64(sac.time_record.start),
+                        decimal_to_f64(sac.time_record.end),
+                    );
+
+                    writeln!(
+                        writer,
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}{}",
+                        rows.prefix,
+                        eye_used(eye),
+                        i + 1,
+                        rows.relative(sac.time_record.start),
+                        rows.relative(sac.time_record.end),
+                        float(Some(duration)),
+                        float(start_position.map(|p| p[0])),
+                        float(start_position.map(|p| p[1])),
+                        float(end_position.map(|p| p[0])),
+                        float(end_position.map(|p| p[1])),
+                        float(amplitude),
+                        float(sac.saccade_angle()),
+                        peak_velocity,
+                        float(amplitude.map(|a| a / duration * 1000.)),
+                        contains_blink,
+                        field(interest_area(areas, start_position).map_or("", |a| a.1)),
+                        field(interest_area(areas, end_position).map_or("", |a| a.1)),
+                        saccades.len(),
+                        rows.trial.time_record.start,
+                        float(Some(rows.dwell)),
+                        rows.variables,
+                    )?;
+                }
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes `sample_report.txt`, `fixation_report.txt` and `saccade_report.txt` to `dir`.
+    pub fn write_reports<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        options: &ReportOptions,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let paths: Vec<PathBuf> = ["sample_report", "fixation_report", "saccade_report"]
+            .iter()
+            .map(|name| dir.join(format!("{name}.txt")))
+            .collect();
+
+        self.write_sample_report(BufWriter::new(File::create(&paths[0])?), options)?;
+        self.write_fixation_report(BufWriter::new(File::create(&paths[1])?), options)?;
+        self.write_saccade_report(BufWriter::new(File::create(&paths[2])?), options)?;
+        Ok(paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asc_to_generic;
+    use std::collections::HashMap;
+
+    const ASC: &str = "\
+MSG\t1000 TRIALID 1
+MSG\t1000 before first sample
+1002\t100.0\t200.0\t1000.0\t.\t.\t0.0\t1.0\t2.0\t.\t.\t30.0\t31.0\t0.0\t.....
+MSG\t1003 stimulus on
+MSG\t1003 sound on
+1004\t102.0\t204.0\t1000.0\t.\t.\t0.0\t1.0\t2.0\t.\t.\t30.0\t31.0\t0.0\t.....
+EFIX L\t1002\t1004\t2\t101.0\t202.0\t1000\t30.0\t31.0
+MSG\t1005 after last sample
+MSG\t1010 TRIAL_RESULT 0
+";
+
+    /// Rows of a report as maps of column name to value.
+    fn rows(report: &[u8]) -> Vec<HashMap<String, String>> {
+        let report = std::str::from_utf8(report).unwrap();
+        let mut lines = report.lines();
+        let header: Vec<&str> = lines.next().unwrap().split('\t').collect();
+        lines
+            .map(|line| {
+                let values = line.split('\t').map(|v| v.to_string());
+                header.iter().map(|h| h.to_string()).zip(values).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sample_report() {
+        let exp = asc_to_generic(ASC).unwrap();
+        let options = ReportOptions {
+            session_label: "P01".to_string(),
+            interest_areas: Vec::new(),
+        };
+        let mut report = Vec::new();
+        exp.write_sample_report(&mut report, &options).unwrap();
+        let rows = rows(&report);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["RECORDING_SESSION_LABEL"], "P01");
+        assert_eq!(rows[0]["TIMESTAMP"], "1002");
+        assert_eq!(rows[0]["EYE_TRACKED"], "Left");
+        assert_eq!(rows[0]["LEFT_GAZE_X"], "100.00");
+        assert_eq!(rows[0]["RIGHT_GAZE_X"], ".");
+        assert_eq!(rows[0]["LEFT_FIX_INDEX"], "1");
+        assert_eq!(rows[1]["AVERAGE_GAZE_Y"], "204.00");
+        // Messages go to the next sample, those after the last sample are dropped
+        assert_eq!(rows[0]["SAMPLE_MESSAGE"], "before first sample");
+        assert_eq!(rows[1]["SAMPLE_MESSAGE"], "stimulus on sound on");
+    }
+
+    #[test]
+    fn test_fixation_report() {
+        let exp = asc_to_generic(ASC).unwrap();
+        let mut report = Vec::new();
+        exp.write_fixation_report(&mut report, &ReportOptions::default())
+            .unwrap();
+        let rows = rows(&report);
+
+        assert_eq!(rows.len(), 1);
+        let row = &rows[0];
+        assert_eq!(row["EYE_USED"], "LEFT");
+        assert_eq!(row["CURRENT_FIX_INDEX"], "1");
+        assert_eq!(row["CURRENT_FIX_START"], "2.00");
+        assert_eq!(row["CURRENT_FIX_DURATION"], "2.00");
+        assert_eq!(row["CURRENT_FIX_X"], "101.00");
+        assert_eq!(row["CURRENT_FIX_INTEREST_AREA_INDEX"], ".");
+        assert_eq!(row["PREVIOUS_SAC_AMPLITUDE"], ".");
+        assert_eq!(row["TRIAL_FIXATION_TOTAL"], "1");
+        assert_eq!(row["TRIAL_DWELL_TIME"], "10.00");
+    }
+}